@@ -0,0 +1,32 @@
+/// A summary of the values below each node of a [`PathTrie`](crate::PathTrie),
+/// such as their sum, their maximum or how many there are.
+///
+/// Summaries are combined like a monoid: `combine` must be associative, with
+/// `empty` as its identity. They are kept up to date on every insert and
+/// remove, so that [`PathTrie::aggregate_prefix`](crate::PathTrie::aggregate_prefix)
+/// never has to visit the values themselves.
+pub trait Aggregate<T> {
+    type Value: Clone;
+
+    /// The summary of no values at all.
+    fn empty() -> Self::Value;
+
+    /// The summary of a single value.
+    fn value(value: &T) -> Self::Value;
+
+    fn combine(left: &Self::Value, right: &Self::Value) -> Self::Value;
+}
+
+/// Keeps no summary at all.
+impl<T> Aggregate<T> for () {
+    type Value = ();
+
+    #[inline]
+    fn empty() {}
+
+    #[inline]
+    fn value(_: &T) {}
+
+    #[inline]
+    fn combine(_: &(), _: &()) {}
+}
@@ -0,0 +1,309 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::{Arc, RwLock};
+
+use crate::{Integer, PathTrie, Streamer};
+
+const DEFAULT_DEPTH: usize = 1;
+
+type Shards<T> = BTreeMap<Box<[u8]>, Arc<RwLock<PathTrie<T>>>>;
+
+/// A [`PathTrie`] that can be inserted into and read from many threads at once.
+///
+/// Keys are split into subtrees by their first path components, each an
+/// ordinary [`PathTrie`] behind its own lock, so writers only contend when
+/// they work in the same subtree. Every key starting with a given subtree
+/// lives in the same trie, and the subtrees are kept in key order, so prefix
+/// queries and ordered walks only lock the subtrees they need.
+/// [`ConcurrentPathTrie::into_trie`] joins them back into one.
+#[derive(Debug)]
+pub struct ConcurrentPathTrie<T: Integer> {
+    depth: usize,
+    shards: RwLock<Shards<T>>,
+}
+
+impl<T: Integer> Default for ConcurrentPathTrie<T> {
+    fn default() -> Self {
+        ConcurrentPathTrie::new()
+    }
+}
+
+impl<T: Integer> ConcurrentPathTrie<T> {
+    /// Splits keys into subtrees by their first path component.
+    #[inline]
+    pub fn new() -> Self {
+        Self::with_depth(DEFAULT_DEPTH)
+    }
+
+    /// Splits keys into subtrees by their first `depth` path components,
+    /// which suits key sets that all share a few leading components.
+    pub fn with_depth(depth: usize) -> Self {
+        assert!(
+            depth > 0,
+            "ConcurrentPathTrie needs a depth of at least one"
+        );
+
+        Self {
+            depth,
+            shards: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    /// The key up to and including its `depth`th `/`, or the whole key if it
+    /// has fewer. A leading `/` isn't counted, so absolute paths are split by
+    /// their first components too.
+    #[inline]
+    fn shard_key<'k>(&self, key: &'k [u8]) -> &'k [u8] {
+        let skip = if key.starts_with(b"/") { 1 } else { 0 };
+
+        key.iter()
+            .enumerate()
+            .skip(skip)
+            .filter(|(_, byte)| **byte == b'/')
+            .nth(self.depth - 1)
+            .map(|(i, _)| &key[..=i])
+            .unwrap_or(key)
+    }
+
+    pub fn insert<K: AsRef<[u8]>>(&self, key: K, value: T) {
+        let key = key.as_ref();
+        let shard_key = self.shard_key(key);
+
+        {
+            let shards = self.shards.read().expect("ConcurrentPathTrie poisoned");
+            if let Some(shard) = shards.get(shard_key) {
+                shard
+                    .write()
+                    .expect("ConcurrentPathTrie shard poisoned")
+                    .insert(key, value);
+                return;
+            }
+        }
+
+        // Another thread may have added the subtree since the read lock was
+        // dropped, in which case this inserts into theirs.
+        let mut shards = self.shards.write().expect("ConcurrentPathTrie poisoned");
+        shards
+            .entry(shard_key.into())
+            .or_default()
+            .write()
+            .expect("ConcurrentPathTrie shard poisoned")
+            .insert(key, value);
+    }
+
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Option<T> {
+        let key = key.as_ref();
+        let shards = self.shards.read().expect("ConcurrentPathTrie poisoned");
+
+        let value = shards
+            .get(self.shard_key(key))?
+            .read()
+            .expect("ConcurrentPathTrie shard poisoned")
+            .get(key);
+        value
+    }
+
+    /// Calls `f` with every key starting with `prefix` and its value, in key
+    /// order. Each subtree's matches are copied out before `f` sees them, so
+    /// no lock is held while it runs and it may use the trie itself. Keys
+    /// inserted meanwhile may or may not be seen.
+    pub fn for_each_prefix<K, F>(&self, prefix: K, mut f: F)
+    where
+        K: AsRef<[u8]>,
+        F: FnMut(&[u8], T),
+    {
+        let prefix = prefix.as_ref();
+
+        // Subtree keys that are a prefix of another can only hold that one
+        // key, so walking the subtrees in order walks the keys in order.
+        let mut candidates = vec![];
+        {
+            let shards = self.shards.read().expect("ConcurrentPathTrie poisoned");
+            let start = Bound::Included(self.shard_key(prefix));
+
+            for (shard_key, shard) in shards.range::<[u8], _>((start, Bound::Unbounded)) {
+                if shard_key.starts_with(prefix) || prefix.starts_with(shard_key) {
+                    candidates.push(Arc::clone(shard));
+                } else if &**shard_key > prefix {
+                    break;
+                }
+            }
+        }
+
+        let mut keys = vec![];
+        let mut entries = vec![];
+        for shard in candidates {
+            {
+                let shard = shard.read().expect("ConcurrentPathTrie shard poisoned");
+                let mut stream = shard.stream_prefix(prefix);
+                while let Some((key, value)) = stream.next() {
+                    keys.extend_from_slice(key);
+                    entries.push((keys.len(), *value));
+                }
+            }
+
+            let mut start = 0;
+            for (end, value) in entries.drain(..) {
+                f(&keys[start..end], value);
+                start = end;
+            }
+            keys.clear();
+        }
+    }
+
+    pub fn into_trie(self) -> PathTrie<T> {
+        let shards = self
+            .shards
+            .into_inner()
+            .expect("ConcurrentPathTrie poisoned")
+            .into_values()
+            .map(|shard| {
+                Arc::try_unwrap(shard)
+                    .expect("ConcurrentPathTrie shard still shared")
+                    .into_inner()
+                    .expect("ConcurrentPathTrie shard poisoned")
+            })
+            .collect::<Vec<_>>();
+
+        // The subtrees are in key order, so their entries are too.
+        PathTrie::from_sorted_iter(
+            shards
+                .iter()
+                .flat_map(|shard| shard.entries().map(|(key, value)| (key, *value))),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn threads() {
+        let trie = Arc::new(ConcurrentPathTrie::<u32>::with_depth(2));
+
+        let handles = (0..8u32)
+            .map(|thread| {
+                let trie = Arc::clone(&trie);
+                std::thread::spawn(move || {
+                    for n in 0..500u32 {
+                        trie.insert(format!("root/{}/{}/file", n % 7, thread * 1000 + n), n);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(trie.shards.read().unwrap().len(), 7);
+        assert_eq!(trie.get("root/3/1003/file"), Some(3));
+        assert_eq!(trie.get("root/3/1003"), None);
+
+        let trie = Arc::try_unwrap(trie).unwrap().into_trie();
+        assert_eq!(trie.keys().count(), 8 * 500);
+        assert_eq!(trie.get("root/0/7497/file"), Some(497));
+    }
+
+    #[test]
+    fn absolute_paths() {
+        let trie = ConcurrentPathTrie::<u32>::new();
+        for (n, key) in ["/usr/bin/ls", "/usr/lib", "/etc/hosts", "/", "/home/a"]
+            .iter()
+            .enumerate()
+        {
+            trie.insert(key, n as u32);
+        }
+
+        let shards = trie.shards.read().unwrap().keys().cloned().collect::<Vec<_>>();
+        assert_eq!(
+            shards,
+            vec![
+                b"/".to_vec().into_boxed_slice(),
+                b"/etc/".to_vec().into(),
+                b"/home/".to_vec().into(),
+                b"/usr/".to_vec().into(),
+            ]
+        );
+        assert_eq!(trie.get("/usr/lib"), Some(1));
+
+        let mut found = vec![];
+        trie.for_each_prefix("/usr/", |key, _| found.push(key.to_vec()));
+        assert_eq!(found, vec![b"/usr/bin/ls".to_vec(), b"/usr/lib".to_vec()]);
+
+        let mut all = vec![];
+        trie.for_each_prefix("/", |key, _| all.push(key.to_vec()));
+        assert_eq!(all.len(), 5);
+    }
+
+    #[test]
+    fn insert_during_prefix_walk() {
+        let trie = ConcurrentPathTrie::<u32>::new();
+        trie.insert("a/1", 1);
+        trie.insert("a/2", 2);
+
+        // Neither the map nor the subtree is locked while the callback runs.
+        trie.for_each_prefix("a/", |key, value| {
+            let mut copy = b"b/".to_vec();
+            copy.extend_from_slice(&key[2..]);
+            trie.insert(copy, value);
+            trie.insert(b"a/3", 3);
+        });
+
+        assert_eq!(trie.get("b/1"), Some(1));
+        assert_eq!(trie.get("b/2"), Some(2));
+        assert_eq!(trie.get("a/3"), Some(3));
+    }
+
+    #[test]
+    fn prefix_order() {
+        let trie = ConcurrentPathTrie::<u32>::new();
+        let keys = [
+            "src",
+            "src/lib.rs",
+            "src.rs",
+            "a/b",
+            "src/a/b.rs",
+            "srcs/x",
+            "b",
+        ];
+        for (n, key) in keys.iter().enumerate() {
+            trie.insert(key, n as u32);
+        }
+
+        let mut all = vec![];
+        trie.for_each_prefix("", |key, value| all.push((key.to_vec(), value)));
+        let mut sorted = keys
+            .iter()
+            .enumerate()
+            .map(|(n, key)| (key.as_bytes().to_vec(), n as u32))
+            .collect::<Vec<_>>();
+        sorted.sort();
+        assert_eq!(all, sorted);
+
+        let mut found = vec![];
+        trie.for_each_prefix("src", |key, _| found.push(key.to_vec()));
+        assert_eq!(
+            found,
+            vec![
+                b"src".to_vec(),
+                b"src.rs".to_vec(),
+                b"src/a/b.rs".to_vec(),
+                b"src/lib.rs".to_vec(),
+                b"srcs/x".to_vec(),
+            ]
+        );
+
+        let mut found = vec![];
+        trie.for_each_prefix("src/l", |key, _| found.push(key.to_vec()));
+        assert_eq!(found, vec![b"src/lib.rs".to_vec()]);
+
+        let trie = trie.into_trie();
+        assert_eq!(
+            trie.keys().map(|key| key.to_vec()).collect::<Vec<_>>(),
+            sorted.into_iter().map(|(key, _)| key).collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,612 @@
+extern crate alloc;
+
+use core::{fmt::Debug, mem::size_of};
+
+use crate::{
+    fst,
+    node::{NodeBody, NodeRef},
+    Aggregate, Fst, Integer, Offset, PathTrie,
+};
+use alloc::{boxed::Box, vec, vec::Vec};
+
+/// A single difference between two tries, as yielded by [`diff`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change<T> {
+    Added(Box<[u8]>, T),
+    Removed(Box<[u8]>, T),
+    Changed(Box<[u8]>, T, T),
+}
+
+mod sealed {
+    pub trait Sealed {}
+    impl<T: crate::Integer, A: crate::Aggregate<T>> Sealed for crate::PathTrie<T, A> {}
+    impl<T, O> Sealed for crate::Fst<'_, T, O> {}
+    impl<T: crate::Integer> Sealed for crate::PersistentPathTrie<T> {}
+}
+
+/// Anything that can be compared with [`diff`]: a [`PathTrie`], a
+/// [`PersistentPathTrie`](crate::PersistentPathTrie) or an [`Fst`].
+pub trait Source<'a, T>: sealed::Sealed {
+    #[doc(hidden)]
+    type Node: Copy;
+
+    #[doc(hidden)]
+    fn roots(&'a self, out: &mut Vec<Self::Node>);
+
+    #[doc(hidden)]
+    fn children(&'a self, node: Self::Node, out: &mut Vec<Self::Node>);
+
+    #[doc(hidden)]
+    fn key(&self, node: Self::Node) -> &'a [u8];
+
+    #[doc(hidden)]
+    fn value(&self, node: Self::Node) -> Option<T>;
+
+    #[doc(hidden)]
+    fn id(&self, node: Self::Node) -> *const u8;
+
+    #[doc(hidden)]
+    fn subtree(&self, _node: Self::Node) -> Option<Subtree<'a>> {
+        None
+    }
+}
+
+/// The serialized groups below a node of an [`Fst`], which hold the same
+/// entries as another's if they have the same bytes, bar offsets.
+#[doc(hidden)]
+pub struct Subtree<'a> {
+    data: &'a [u8],
+    start: usize,
+    offset_width: usize,
+    value_width: usize,
+}
+
+impl Subtree<'_> {
+    /// Walks both a node at a time, as offsets only match relative to the
+    /// start of each.
+    fn same_as(&self, other: &Subtree<'_>) -> bool {
+        let (a, b) = (self.data, other.data);
+        if self.offset_width != other.offset_width
+            || self.value_width != other.value_width
+            || a.len() != b.len()
+        {
+            return false;
+        }
+
+        let width = self.offset_width;
+        let alignment = width.max(self.value_width);
+        let read = |bytes: &[u8]| {
+            bytes
+                .iter()
+                .rev()
+                .fold(0u128, |acc, byte| acc << 8 | *byte as u128)
+        };
+        let terminus = u128::MAX >> (128 - 8 * width);
+
+        let mut pos = 0;
+        while pos < a.len() {
+            let (x, y) = match (a.get(pos..pos + width), b.get(pos..pos + width)) {
+                (Some(x), Some(y)) => (read(x), read(y)),
+                _ => return false,
+            };
+
+            let mut end = pos + width;
+            if x == 0 || y == 0 {
+                if x != y {
+                    return false;
+                }
+                pos += alignment;
+                continue;
+            } else if x == terminus || y == terminus {
+                if x != y {
+                    return false;
+                }
+                end += self.value_width;
+            } else if x.wrapping_sub(self.start as u128) != y.wrapping_sub(other.start as u128) {
+                return false;
+            }
+
+            end += match a.get(end..).and_then(fst::read_len) {
+                Some((len, size)) => size + len,
+                None => return false,
+            };
+            end += (alignment - end % alignment) % alignment;
+
+            match (a.get(pos + width..end), b.get(pos + width..end)) {
+                (Some(x), Some(y)) if x == y => pos = end,
+                _ => return false,
+            }
+        }
+
+        true
+    }
+}
+
+impl<'a, T: Integer + 'a, A: Aggregate<T> + 'a> Source<'a, T> for PathTrie<T, A> {
+    type Node = NodeRef<'a, T>;
+
+    fn roots(&'a self, out: &mut Vec<Self::Node>) {
+        out.extend(self.root().children());
+    }
+
+    fn children(&'a self, node: Self::Node, out: &mut Vec<Self::Node>) {
+        out.extend(node.children());
+    }
+
+    fn key(&self, node: Self::Node) -> &'a [u8] {
+        node.key()
+    }
+
+    fn value(&self, node: Self::Node) -> Option<T> {
+        match node.body() {
+            NodeBody::Value(value) => Some(*value),
+            NodeBody::Children(_) => None,
+        }
+    }
+
+    fn id(&self, node: Self::Node) -> *const u8 {
+        node.body() as *const _ as *const u8
+    }
+}
+
+impl<'a, 'data: 'a, T, O> Source<'a, T> for Fst<'data, T, O>
+where
+    T: Integer + Debug + 'data,
+    O: Offset + 'data,
+{
+    type Node = fst::Node<'data, T, O>;
+
+    fn roots(&'a self, out: &mut Vec<Self::Node>) {
+        out.extend(Fst::roots(self));
+    }
+
+    fn children(&'a self, node: Self::Node, out: &mut Vec<Self::Node>) {
+        out.extend(Fst::children(self, node));
+    }
+
+    fn key(&self, node: Self::Node) -> &'a [u8] {
+        node.key()
+    }
+
+    fn value(&self, node: Self::Node) -> Option<T> {
+        node.terminus()
+    }
+
+    fn id(&self, node: Self::Node) -> *const u8 {
+        self.data()[self.offset_of(node)..].as_ptr()
+    }
+
+    fn subtree(&self, node: Self::Node) -> Option<Subtree<'a>> {
+        let range = self.subtree_of(node)?;
+        Some(Subtree {
+            start: range.start,
+            data: &self.data()[range],
+            offset_width: size_of::<O>(),
+            value_width: size_of::<T>(),
+        })
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Cursor<N> {
+    node: N,
+    offset: usize,
+}
+
+enum Work<T, A, B> {
+    Level {
+        depth: usize,
+        old: Vec<Cursor<A>>,
+        new: Vec<Cursor<B>>,
+    },
+    Pair {
+        depth: usize,
+        old: Cursor<A>,
+        new: Cursor<B>,
+    },
+    Old {
+        depth: usize,
+        cursor: Cursor<A>,
+    },
+    New {
+        depth: usize,
+        cursor: Cursor<B>,
+    },
+    End {
+        depth: usize,
+        old: Option<T>,
+        new: Option<T>,
+    },
+}
+
+/// Iterator over the [`Change`]s between two tries, in key order.
+///
+/// Both sides are walked together one edge at a time, so parts that match
+/// are never turned into keys. A subtree is skipped without being visited
+/// when both sides hold the very same node, such as two versions of a
+/// [`PersistentPathTrie`](crate::PersistentPathTrie) sharing it, or when both
+/// sides are [`Fst`]s whose bytes below it match. Separately built tries are
+/// compared edge by edge all the way down, even where their contents match.
+pub struct Diff<'a, T, A: Source<'a, T>, B: Source<'a, T>> {
+    old: &'a A,
+    new: &'a B,
+    key: Vec<u8>,
+    stack: Vec<Work<T, A::Node, B::Node>>,
+}
+
+/// Compares `old` with `new`, yielding every key that was added, removed or
+/// changed in key order.
+pub fn diff<'a, T, A, B>(old: &'a A, new: &'a B) -> Diff<'a, T, A, B>
+where
+    T: Integer,
+    A: Source<'a, T>,
+    B: Source<'a, T>,
+{
+    let mut nodes = vec![];
+    let mut old_level = vec![];
+    old.roots(&mut nodes);
+    for node in nodes.drain(..) {
+        descend(old, Cursor { node, offset: 0 }, &mut old_level);
+    }
+
+    let mut nodes = vec![];
+    let mut new_level = vec![];
+    new.roots(&mut nodes);
+    for node in nodes.drain(..) {
+        descend(new, Cursor { node, offset: 0 }, &mut new_level);
+    }
+
+    Diff {
+        old,
+        new,
+        key: vec![],
+        stack: vec![Work::Level {
+            depth: 0,
+            old: old_level,
+            new: new_level,
+        }],
+    }
+}
+
+#[inline]
+fn remaining<'a, T, S: Source<'a, T>>(source: &S, cursor: &Cursor<S::Node>) -> &'a [u8] {
+    &source.key(cursor.node)[cursor.offset..]
+}
+
+/// Pushes `cursor` onto `out`, replacing it with its children if the cursor
+/// has consumed the whole key of a node with children.
+fn descend<'a, T, S: Source<'a, T>>(
+    source: &'a S,
+    cursor: Cursor<S::Node>,
+    out: &mut Vec<Cursor<S::Node>>,
+) {
+    if !remaining(source, &cursor).is_empty() || source.value(cursor.node).is_some() {
+        out.push(cursor);
+        return;
+    }
+
+    let mut children = vec![];
+    source.children(cursor.node, &mut children);
+    for node in children {
+        descend(source, Cursor { node, offset: 0 }, out);
+    }
+}
+
+impl<'a, T, A, B> Diff<'a, T, A, B>
+where
+    T: Integer,
+    A: Source<'a, T>,
+    B: Source<'a, T>,
+{
+    fn level(
+        &mut self,
+        depth: usize,
+        mut old: Vec<Cursor<A::Node>>,
+        mut new: Vec<Cursor<B::Node>>,
+    ) {
+        let (old_source, new_source) = (self.old, self.new);
+        old.sort_by_key(|c| remaining(old_source, c).first().copied());
+        new.sort_by_key(|c| remaining(new_source, c).first().copied());
+
+        let mut work = vec![];
+        let (mut old_end, mut new_end) = (None, None);
+        let (mut old, mut new) = (old.into_iter().peekable(), new.into_iter().peekable());
+
+        loop {
+            let a = old
+                .peek()
+                .map(|c| remaining(old_source, c).first().copied());
+            let b = new
+                .peek()
+                .map(|c| remaining(new_source, c).first().copied());
+
+            match (a, b) {
+                (None, None) => break,
+                (Some(a), Some(b)) if a == b => {
+                    let (old, new) = (old.next().unwrap(), new.next().unwrap());
+                    match a {
+                        None => {
+                            old_end = old_source.value(old.node);
+                            new_end = new_source.value(new.node);
+                        }
+                        Some(_) => work.push(Work::Pair { depth, old, new }),
+                    }
+                }
+                (Some(a), b) if b.is_none() || Some(a) < b => {
+                    let cursor = old.next().unwrap();
+                    match a {
+                        None => old_end = old_source.value(cursor.node),
+                        Some(_) => work.push(Work::Old { depth, cursor }),
+                    }
+                }
+                (_, Some(b)) => {
+                    let cursor = new.next().unwrap();
+                    match b {
+                        None => new_end = new_source.value(cursor.node),
+                        Some(_) => work.push(Work::New { depth, cursor }),
+                    }
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        self.stack.extend(work.into_iter().rev());
+        if old_end.is_some() || new_end.is_some() {
+            self.stack.push(Work::End {
+                depth,
+                old: old_end,
+                new: new_end,
+            });
+        }
+    }
+
+    #[inline]
+    fn current_key(&self) -> Box<[u8]> {
+        self.key.to_vec().into_boxed_slice()
+    }
+}
+
+impl<'a, T, A, B> Iterator for Diff<'a, T, A, B>
+where
+    T: Integer,
+    A: Source<'a, T>,
+    B: Source<'a, T>,
+{
+    type Item = Change<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.stack.pop()? {
+                Work::Level { depth, old, new } => self.level(depth, old, new),
+                Work::Pair { depth, old, new } => {
+                    if old.offset == 0
+                        && new.offset == 0
+                        && self.old.id(old.node) == self.new.id(new.node)
+                    {
+                        // Both sides point at the same node, so nothing below it can differ.
+                        continue;
+                    }
+
+                    let a = remaining(self.old, &old);
+                    let b = remaining(self.new, &new);
+                    if a == b {
+                        let old = self.old.subtree(old.node);
+                        let new = self.new.subtree(new.node);
+                        if let (Some(old), Some(new)) = (old, new) {
+                            if old.same_as(&new) {
+                                continue;
+                            }
+                        }
+                    }
+                    let len = a.iter().zip(b.iter()).take_while(|(x, y)| x == y).count();
+
+                    self.key.truncate(depth);
+                    self.key.extend_from_slice(&a[..len]);
+
+                    let (mut olds, mut news) = (vec![], vec![]);
+                    descend(
+                        self.old,
+                        Cursor {
+                            node: old.node,
+                            offset: old.offset + len,
+                        },
+                        &mut olds,
+                    );
+                    descend(
+                        self.new,
+                        Cursor {
+                            node: new.node,
+                            offset: new.offset + len,
+                        },
+                        &mut news,
+                    );
+                    self.stack.push(Work::Level {
+                        depth: depth + len,
+                        old: olds,
+                        new: news,
+                    });
+                }
+                Work::Old { depth, cursor } => {
+                    self.key.truncate(depth);
+                    self.key.extend_from_slice(remaining(self.old, &cursor));
+
+                    if let Some(value) = self.old.value(cursor.node) {
+                        return Some(Change::Removed(self.current_key(), value));
+                    }
+
+                    let mut children = vec![];
+                    self.old.children(cursor.node, &mut children);
+                    let depth = self.key.len();
+                    self.stack
+                        .extend(children.into_iter().rev().map(|node| Work::Old {
+                            depth,
+                            cursor: Cursor { node, offset: 0 },
+                        }));
+                }
+                Work::New { depth, cursor } => {
+                    self.key.truncate(depth);
+                    self.key.extend_from_slice(remaining(self.new, &cursor));
+
+                    if let Some(value) = self.new.value(cursor.node) {
+                        return Some(Change::Added(self.current_key(), value));
+                    }
+
+                    let mut children = vec![];
+                    self.new.children(cursor.node, &mut children);
+                    let depth = self.key.len();
+                    self.stack
+                        .extend(children.into_iter().rev().map(|node| Work::New {
+                            depth,
+                            cursor: Cursor { node, offset: 0 },
+                        }));
+                }
+                Work::End { depth, old, new } => {
+                    self.key.truncate(depth);
+
+                    match (old, new) {
+                        (Some(old), Some(new)) if old != new => {
+                            return Some(Change::Changed(self.current_key(), old, new))
+                        }
+                        (Some(old), None) => return Some(Change::Removed(self.current_key(), old)),
+                        (None, Some(new)) => return Some(Change::Added(self.current_key(), new)),
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn trie(paths: &[(&str, u32)]) -> PathTrie<u32> {
+        let mut trie = PathTrie::new();
+        for (path, value) in paths {
+            trie.insert(path, *value);
+        }
+        trie
+    }
+
+    fn changes<'a, A: Source<'a, u32>, B: Source<'a, u32>>(
+        old: &'a A,
+        new: &'a B,
+    ) -> Vec<(String, Option<u32>, Option<u32>)> {
+        diff(old, new)
+            .map(|change| match change {
+                Change::Added(k, v) => (String::from_utf8_lossy(&k).to_string(), None, Some(v)),
+                Change::Removed(k, v) => (String::from_utf8_lossy(&k).to_string(), Some(v), None),
+                Change::Changed(k, a, b) => {
+                    (String::from_utf8_lossy(&k).to_string(), Some(a), Some(b))
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn diff_tries_and_fsts() {
+        let old = trie(&[
+            ("bacon/sandwich/ham", 1),
+            ("bacon/sandwich/hamburger", 2),
+            ("bacon/jam", 3),
+            ("bacon", 4),
+            ("apple", 5),
+        ]);
+        let new = trie(&[
+            ("bacon/sandwich/ham", 1),
+            ("bacon/sandwich/hamburger", 20),
+            ("bacon/sandwich/hamster", 6),
+            ("bacon", 4),
+            ("app", 7),
+            ("apple", 5),
+        ]);
+
+        let expected = vec![
+            ("app".to_string(), None, Some(7)),
+            ("bacon/jam".to_string(), Some(3), None),
+            ("bacon/sandwich/hamburger".to_string(), Some(2), Some(20)),
+            ("bacon/sandwich/hamster".to_string(), None, Some(6)),
+        ];
+        assert_eq!(changes(&old, &new), expected);
+        assert_eq!(changes(&old, &old), vec![]);
+
+        let mut old_buf = Cursor::new(vec![]);
+        old.write_fst(&mut old_buf).unwrap();
+        let old_buf = old_buf.into_inner();
+        let old_fst = Fst::<u32>::new(&old_buf).unwrap();
+
+        let mut new_buf = Cursor::new(vec![]);
+        new.write_fst(&mut new_buf).unwrap();
+        let new_buf = new_buf.into_inner();
+        let new_fst = Fst::<u32>::new(&new_buf).unwrap();
+
+        assert_eq!(changes(&old_fst, &new_fst), expected);
+        assert_eq!(changes(&old, &new_fst), expected);
+        assert_eq!(changes(&old_fst, &new), expected);
+        assert_eq!(changes(&old_fst, &old_fst), vec![]);
+    }
+
+    #[test]
+    fn skip_matching_fst_subtrees() {
+        let mut old = PathTrie::<u32>::new();
+        let mut new = PathTrie::<u32>::new();
+        for n in 0..500u32 {
+            old.insert(format!("big/{}/file", n), n);
+            new.insert(format!("big/{}/file", n), n);
+            old.insert(format!("mid/{}", n), n);
+            new.insert(format!("mid/{}", n), if n == 42 { 0 } else { n });
+        }
+        // Shifts everything after it in the new FST.
+        for n in 0..50u32 {
+            new.insert(format!("a/{}", n), n);
+        }
+        old.insert("x/1", 1);
+        new.insert("x/1", 2);
+
+        let mut old_buf = Cursor::new(vec![]);
+        old.write_fst(&mut old_buf).unwrap();
+        let old_buf = old_buf.into_inner();
+        let old_fst = Fst::<u32>::new(&old_buf).unwrap();
+
+        let mut new_buf = Cursor::new(vec![]);
+        new.write_fst(&mut new_buf).unwrap();
+        let new_buf = new_buf.into_inner();
+        let new_fst = Fst::<u32>::new(&new_buf).unwrap();
+
+        let expected = changes(&old, &new);
+        assert_eq!(expected.len(), 52);
+        assert_eq!(expected[50], ("mid/42".to_string(), Some(42), Some(0)));
+        assert_eq!(changes(&old_fst, &new_fst), expected);
+
+        fn root<'a>(fst: &'a Fst<'a, u32>, key: &[u8]) -> Subtree<'a> {
+            let node = fst.roots().find(|node| node.key() == key).unwrap();
+            Source::subtree(fst, node).unwrap()
+        }
+        assert!(root(&old_fst, b"big/").same_as(&root(&new_fst, b"big/")));
+        assert!(!root(&old_fst, b"mid/").same_as(&root(&new_fst, b"mid/")));
+        assert!(!root(&old_fst, b"big/").same_as(&root(&old_fst, b"mid/")));
+    }
+
+    #[test]
+    fn diff_empty() {
+        let empty = PathTrie::<u32>::new();
+        let full = trie(&[("a/b", 1), ("a", 2)]);
+
+        assert_eq!(
+            changes(&empty, &full),
+            vec![
+                ("a".to_string(), None, Some(2)),
+                ("a/b".to_string(), None, Some(1))
+            ]
+        );
+        assert_eq!(
+            changes(&full, &empty),
+            vec![
+                ("a".to_string(), Some(2), None),
+                ("a/b".to_string(), Some(1), None)
+            ]
+        );
+    }
+}
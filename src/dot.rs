@@ -0,0 +1,144 @@
+extern crate alloc;
+
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt::{Debug, Write};
+
+use crate::{node::NodeBody, Aggregate, Fst, Integer, Offset, PathTrie};
+
+/// Quotes `bytes` for use as a DOT label.
+fn quote(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() + 2);
+    out.push('"');
+    for c in String::from_utf8_lossy(bytes).chars() {
+        match c {
+            '"' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+impl<T: Integer, A: Aggregate<T>> PathTrie<T, A> {
+    /// Renders the nodes of the trie as a Graphviz graph, with each edge
+    /// labelled by the key of the node it leads to.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph PathTrie {\n    node [shape=box];\n");
+        let mut stack = alloc::vec![self.root()];
+
+        while let Some(node) = stack.pop() {
+            let id = node.id();
+
+            // Writing to a String never fails.
+            match node.body() {
+                NodeBody::Children(_) => {
+                    writeln!(out, "    n{} [label=\"#{}\"];", id, id).unwrap();
+                }
+                NodeBody::Value(value) => {
+                    let label = quote(value.to_string().as_bytes());
+                    writeln!(out, "    n{} [label={}, shape=ellipse];", id, label).unwrap();
+                }
+            }
+
+            for child in node.children() {
+                writeln!(
+                    out,
+                    "    n{} -> n{} [label={}];",
+                    id,
+                    child.id(),
+                    quote(child.key())
+                )
+                .unwrap();
+            }
+
+            let start = stack.len();
+            stack.extend(node.children());
+            stack[start..].reverse();
+        }
+
+        out.push_str("}\n");
+        out
+    }
+}
+
+impl<T: Integer + Debug, O: Offset> Fst<'_, T, O> {
+    /// Renders the nodes of the FST as a Graphviz graph. Each node is named by
+    /// its byte offset, and each edge is labelled by the key of the node it
+    /// leads to.
+    pub fn to_dot(&self) -> String {
+        let mut out =
+            String::from("digraph Fst {\n    node [shape=box];\n    root [label=\"\"];\n");
+        let mut stack = self.roots().map(|x| (None, x)).collect::<Vec<_>>();
+        stack.reverse();
+
+        while let Some((parent, node)) = stack.pop() {
+            let offset = self.offset_of(node);
+
+            // Writing to a String never fails.
+            match node.terminus() {
+                Some(value) => {
+                    let label = quote(alloc::format!("@{}\n{}", offset, value).as_bytes());
+                    writeln!(out, "    n{} [label={}, shape=ellipse];", offset, label).unwrap();
+                }
+                None => writeln!(out, "    n{} [label=\"@{}\"];", offset, offset).unwrap(),
+            }
+
+            match parent {
+                Some(parent) => write!(out, "    n{}", parent).unwrap(),
+                None => out.push_str("    root"),
+            }
+            writeln!(out, " -> n{} [label={}];", offset, quote(node.key())).unwrap();
+
+            let start = stack.len();
+            stack.extend(self.children(node).map(|x| (Some(offset), x)));
+            stack[start..].reverse();
+        }
+
+        out.push_str("}\n");
+        out
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use memmap::Mmap;
+    use std::fs::File;
+    use std::io::Cursor;
+
+    #[test]
+    fn to_dot() {
+        let mut trie = PathTrie::<u32>::new();
+        trie.insert("ab", 1);
+        trie.insert("ac", 2);
+        trie.insert("a", 3);
+        trie.insert("\"b\"", 4);
+
+        let dot = trie.to_dot();
+        println!("{}", dot);
+        assert!(dot.starts_with("digraph PathTrie {\n"));
+        assert!(dot.contains("[label=\"\\\"b\\\"\"]"));
+        assert_eq!(dot.matches("->").count(), 5);
+        assert_eq!(dot.matches("shape=ellipse").count(), 4);
+
+        let mut buf = Cursor::new(vec![]);
+        trie.write_fst(&mut buf).unwrap();
+        std::fs::write("./test-dot.fst", buf.into_inner()).unwrap();
+        let mmap = unsafe { Mmap::map(&File::open("./test-dot.fst").unwrap()).unwrap() };
+        let fst = Fst::<u32>::new(&mmap).unwrap();
+
+        let dot = fst.to_dot();
+        println!("{}", dot);
+        assert!(dot.starts_with("digraph Fst {\n"));
+        assert!(dot.contains("root -> n8 [label=\"\\\"b\\\"\"];"));
+        assert_eq!(dot.matches("->").count(), 5);
+        assert_eq!(dot.matches("shape=ellipse").count(), 4);
+    }
+}
@@ -6,51 +6,236 @@ use core::{
     fmt::{self, Debug},
     marker::PhantomData,
     mem::size_of,
-    num::NonZeroU32,
+    ops::Range,
 };
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(feature = "alloc")]
+use alloc::string::String;
+
 #[derive(Debug)]
 #[cfg_attr(feature = "std", derive(thiserror::Error))]
 pub enum Error {
     #[cfg_attr(feature = "std", error("Got invalid magic bytes: {0:?}"))]
     InvalidMagicBytes([u8; 2]),
 
-    #[cfg_attr(feature = "std", error("Invalid alignment. Required: {1}, got: {0}"))]
-    InvalidAlignment(u8, usize),
+    #[cfg_attr(
+        feature = "std",
+        error("Invalid offset width. Required: {1}, got: {0}")
+    )]
+    InvalidOffsetWidth(u8, usize),
+
+    #[cfg_attr(feature = "std", error("Invalid value width. Required: {1}, got: {0}"))]
+    InvalidValueWidth(u8, usize),
+
+    #[cfg_attr(feature = "std", error("Unsupported version: {0}"))]
+    UnsupportedVersion(u8),
 
     #[cfg_attr(feature = "std", error("FST too small to be valid"))]
     TooSmall,
+
+    #[cfg_attr(feature = "std", error("Corrupt node at offset {0}"))]
+    Corrupt(usize),
+}
+
+/// Why [`PathTrie::write_fst`](crate::PathTrie::write_fst) failed.
+#[derive(Debug)]
+#[cfg_attr(feature = "std", derive(thiserror::Error))]
+pub enum WriteError {
+    #[cfg_attr(feature = "std", error("Offset {offset} doesn't fit in {width} bytes"))]
+    OffsetOverflow { offset: u64, width: usize },
+
+    #[cfg_attr(
+        feature = "std",
+        error("Buffer too small. Required: {needed}, got: {got}")
+    )]
+    BufferTooSmall { needed: usize, got: usize },
+
+    #[cfg_attr(feature = "std", error("I/O error: {0}"))]
+    Io(#[cfg_attr(feature = "std", source)] bare_io::Error),
+}
+
+impl From<bare_io::Error> for WriteError {
+    #[inline]
+    fn from(err: bare_io::Error) -> Self {
+        WriteError::Io(err)
+    }
+}
+
+/// The format version written to and expected in the header.
+///
+/// Version 1 stores key lengths as LEB128 varints rather than a single byte,
+/// and version 2 records the widths of offsets and values separately.
+pub(crate) const VERSION: u8 = 2;
+
+/// The width of the offsets from each node to its children. `u32` is enough
+/// for FSTs up to 4 GiB, and `u64` covers the rest.
+pub trait Offset: Integer {
+    #[doc(hidden)]
+    const TERMINUS: Self;
+
+    #[doc(hidden)]
+    fn to_usize(self) -> usize;
+}
+
+impl Offset for u32 {
+    const TERMINUS: Self = u32::MAX;
+
+    #[inline]
+    fn to_usize(self) -> usize {
+        self as usize
+    }
+}
+
+impl Offset for u64 {
+    const TERMINUS: Self = u64::MAX;
+
+    #[inline]
+    fn to_usize(self) -> usize {
+        self as usize
+    }
+}
+
+/// Only used to reach the offset limit in tests without writing 4 GiB.
+#[cfg(test)]
+impl Offset for u16 {
+    const TERMINUS: Self = u16::MAX;
+
+    #[inline]
+    fn to_usize(self) -> usize {
+        self as usize
+    }
+}
+
+/// Returns what nodes are aligned to, which is also the size of the empty
+/// node that ends each group of siblings.
+#[inline]
+pub(crate) fn alignment<T, O>() -> usize {
+    size_of::<T>().max(size_of::<O>())
+}
+
+/// Returns the number of bytes needed after `pos` to align it.
+#[inline]
+pub(crate) fn padding<T, O>(pos: usize) -> usize {
+    let alignment = alignment::<T, O>();
+    (alignment - pos % alignment) % alignment
+}
+
+/// Returns the position of the first node, after the header.
+#[inline]
+pub(crate) fn start_offset<T, O>() -> usize {
+    HEADER_SIZE + padding::<T, O>(HEADER_SIZE)
+}
+
+/// Returns the number of bytes `len` takes as a varint.
+#[cfg(feature = "alloc")]
+#[inline]
+pub(crate) fn len_size(mut len: usize) -> usize {
+    let mut size = 1;
+    while len >= 0x80 {
+        len >>= 7;
+        size += 1;
+    }
+    size
+}
+
+/// Writes `len` as a LEB128 varint.
+#[cfg(feature = "alloc")]
+pub(crate) fn write_len<W: bare_io::Write>(
+    writer: &mut W,
+    mut len: usize,
+) -> Result<(), bare_io::Error> {
+    while len >= 0x80 {
+        writer.write_all(&[len as u8 | 0x80])?;
+        len >>= 7;
+    }
+    writer.write_all(&[len as u8])
+}
+
+/// Reads a LEB128 varint from the start of `bytes`, returning it and the
+/// number of bytes it took, or `None` if it runs off the end or overflows.
+#[inline]
+pub(crate) fn read_len(bytes: &[u8]) -> Option<(usize, usize)> {
+    let mut len = 0usize;
+    for (size, byte) in bytes.iter().enumerate() {
+        let shift = 7 * size as u32;
+        let part = (byte & 0x7f) as usize;
+        let shifted = part.checked_shl(shift)?;
+        if shifted >> shift != part {
+            return None;
+        }
+
+        len |= shifted;
+        if byte & 0x80 == 0 {
+            return Some((len, size + 1));
+        }
+    }
+    None
 }
 
-pub struct Fst<'data, T> {
+/// A node read with every offset and length checked against the data.
+struct CheckedNode<'data, T> {
+    next: OffsetKind,
+    value: Option<T>,
+    key: &'data [u8],
+    /// Where the node after this one starts.
+    end: usize,
+}
+
+pub struct Fst<'data, T, O = u32> {
     data: &'data [u8],
-    marker: PhantomData<T>,
+    marker: PhantomData<(T, O)>,
 }
 
-impl<T> Debug for Fst<'_, T> {
+impl<T, O> Debug for Fst<'_, T, O> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Fst<{}> {{ .. }}", core::any::type_name::<T>())
+        write!(
+            f,
+            "Fst<{}, {}> {{ .. }}",
+            core::any::type_name::<T>(),
+            core::any::type_name::<O>()
+        )
     }
 }
 
-impl<T> Fst<'_, T>
+impl<'data, T, O> Fst<'data, T, O>
 where
     T: Integer + Debug,
+    O: Offset,
 {
-    pub fn new(data: &[u8]) -> Result<Fst<'_, T>, Error> {
-        if data.len() < size_of::<Header>() {
+    /// Checks the header of `data`, which can start anywhere, as every
+    /// field is read a byte at a time. Nothing past the header is checked,
+    /// so lookups in malformed data panic.
+    pub fn new(data: &'data [u8]) -> Result<Fst<'data, T, O>, Error> {
+        if data.len() < HEADER_SIZE {
             return Err(Error::TooSmall);
         }
 
-        let header_ptr = data.as_ptr() as *const _ as *const Header;
-        let header = unsafe { &*header_ptr };
+        let header = Header {
+            magic_bytes: [data[0], data[1]],
+            version: data[2],
+            offset_width: data[3],
+            value_width: data[4],
+        };
 
         if header.magic_bytes != [b'\xff', b'\xdf'] {
             return Err(Error::InvalidMagicBytes(header.magic_bytes));
         }
 
-        if header.alignment != size_of::<T>() as u8 {
-            return Err(Error::InvalidAlignment(header.alignment, size_of::<T>()));
+        if header.version != VERSION {
+            return Err(Error::UnsupportedVersion(header.version));
+        }
+
+        if header.offset_width != size_of::<O>() as u8 {
+            return Err(Error::InvalidOffsetWidth(
+                header.offset_width,
+                size_of::<O>(),
+            ));
+        }
+
+        if header.value_width != size_of::<T>() as u8 {
+            return Err(Error::InvalidValueWidth(header.value_width, size_of::<T>()));
         }
 
         Ok(Fst {
@@ -59,52 +244,218 @@ where
         })
     }
 
+    /// Checks every node before returning, so that a corrupt or malicious
+    /// FST is caught here rather than read out of bounds later.
+    ///
+    /// Nodes are read in order from the start, following the groups of
+    /// siblings until every group referred to has ended. Any bytes after
+    /// that, such as the lists of a [`MultiFst`](crate::MultiFst), are left alone.
+    #[cfg(feature = "alloc")]
+    pub fn new_validated(data: &'data [u8]) -> Result<Fst<'data, T, O>, Error> {
+        let fst = Self::new(data)?;
+
+        let mut offset = start_offset::<T, O>();
+        let mut groups = alloc::vec![offset];
+        let mut children = alloc::vec![];
+        let mut ended = 0;
+
+        loop {
+            let node = fst.read_node(offset)?;
+            match node.next {
+                OffsetKind::Empty => {
+                    ended += 1;
+                    if ended > children.len() {
+                        break;
+                    }
+                    groups.push(node.end);
+                }
+                OffsetKind::Offset(child) => children.push(child),
+                OffsetKind::Terminating => {}
+            }
+            offset = node.end;
+        }
+
+        // Group starts were found in order, so they're already sorted.
+        for child in children {
+            if groups.binary_search(&child).is_err() {
+                return Err(Error::Corrupt(child));
+            }
+        }
+
+        Ok(fst)
+    }
+
+    /// Reads the node at `offset`, checking that it lies within the data,
+    /// and that its children come after it.
+    fn read_node(&self, offset: usize) -> Result<CheckedNode<'data, T>, Error> {
+        let corrupt = Error::Corrupt(offset);
+        let data = self.data;
+        if padding::<T, O>(offset) != 0 {
+            return Err(corrupt);
+        }
+
+        let raw = data
+            .get(offset..offset + size_of::<O>())
+            .ok_or(Error::Corrupt(offset))?;
+        let next = O::read_le_bytes(raw);
+        let mut pos = offset + size_of::<O>();
+
+        let (next, value) = if next == O::default() {
+            let end = offset + alignment::<T, O>();
+            if end > data.len() {
+                return Err(corrupt);
+            }
+
+            return Ok(CheckedNode {
+                next: OffsetKind::Empty,
+                value: None,
+                key: &[],
+                end,
+            });
+        } else if next == O::TERMINUS {
+            let raw = data
+                .get(pos..pos + size_of::<T>())
+                .ok_or(Error::Corrupt(offset))?;
+            pos += size_of::<T>();
+            (OffsetKind::Terminating, Some(T::read_le_bytes(raw)))
+        } else {
+            let child = next.to_usize();
+            if child <= offset || child >= data.len() || padding::<T, O>(child) != 0 {
+                return Err(corrupt);
+            }
+            (OffsetKind::Offset(child), None)
+        };
+
+        let (len, size) = data
+            .get(pos..)
+            .and_then(read_len)
+            .ok_or(Error::Corrupt(offset))?;
+        pos += size;
+        let key = pos
+            .checked_add(len)
+            .and_then(|end| data.get(pos..end))
+            .ok_or(Error::Corrupt(offset))?;
+        pos += len;
+
+        let end = pos + padding::<T, O>(pos);
+        if end > data.len() {
+            return Err(corrupt);
+        }
+
+        Ok(CheckedNode {
+            next,
+            value,
+            key,
+            end,
+        })
+    }
+
     #[inline(always)]
-    fn node_at(&self, offset: usize) -> &Node<T> {
+    fn node_at(&self, offset: usize) -> Node<'data, T, O> {
         tracing::trace!("Node at: {}", offset);
-        unsafe { &*(self.data.as_ptr().add(offset) as *const Node<T>) }
+        Node {
+            data: self.data,
+            offset,
+            marker: PhantomData,
+        }
     }
 
     #[inline(always)]
-    fn node_after(&self, node: &Node<T>) -> &Node<T> {
+    fn node_after(&self, node: Node<'data, T, O>) -> Node<'data, T, O> {
         #[cfg(feature = "alloc")]
         tracing::trace!("Node after: {:?}", node);
-        let ptr = node as *const _ as *const u8;
-        let offset_ptr = unsafe { ptr.add(node.len()) };
-        tracing::trace!(
-            "After offset: {}",
-            offset_ptr as usize - self.data.as_ptr() as usize
-        );
+        let offset = node.offset + node.len();
+        tracing::trace!("After offset: {}", offset);
+        self.node_at(offset)
+    }
+
+    /// Returns the position of `node` in the data.
+    #[inline]
+    pub(crate) fn offset_of(&self, node: Node<'data, T, O>) -> usize {
+        node.offset
+    }
+
+    /// Returns the position just past `node` and its padding.
+    #[inline]
+    pub(crate) fn end_of(&self, node: Node<'data, T, O>) -> usize {
+        node.offset + node.len()
+    }
+
+    #[inline]
+    pub(crate) fn data(&self) -> &'data [u8] {
+        self.data
+    }
+
+    /// Returns where the groups below `node` start and end. They follow one
+    /// another first child first, so the last is reached by following the
+    /// last sibling with children of each group.
+    pub(crate) fn subtree_of(&self, node: Node<'data, T, O>) -> Option<Range<usize>> {
+        let start = match node.next_offset() {
+            OffsetKind::Offset(offset) => offset,
+            _ => return None,
+        };
+
+        let mut group = self.node_at(start);
+        loop {
+            let mut node = group;
+            let mut last = None;
+            loop {
+                match node.next_offset() {
+                    OffsetKind::Empty => break,
+                    OffsetKind::Offset(offset) => last = Some(offset),
+                    OffsetKind::Terminating => {}
+                }
+                node = self.node_after(node);
+            }
 
-        unsafe { &*(offset_ptr as *const Node<T>) }
+            match last {
+                Some(offset) => group = self.node_at(offset),
+                None => return Some(start..self.end_of(node)),
+            }
+        }
+    }
+
+    pub(crate) fn roots(&self) -> Siblings<'_, 'data, T, O> {
+        Siblings {
+            fst: self,
+            next: Some(self.node_at(start_offset::<T, O>())),
+        }
+    }
+
+    pub(crate) fn children(&self, node: Node<'data, T, O>) -> Siblings<'_, 'data, T, O> {
+        let next = match node.next_offset() {
+            OffsetKind::Offset(offset) => Some(self.node_at(offset)),
+            _ => None,
+        };
+
+        Siblings { fst: self, next }
     }
 
     pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Option<T> {
-        let start_offset = size_of::<Header>() + (size_of::<Header>() % size_of::<T>());
         let mut key = key.as_ref();
-        let mut current_node = self.node_at(start_offset);
+        let mut current_node = self.node_at(start_offset::<T, O>());
 
         loop {
             #[cfg(feature = "alloc")]
             tracing::trace!(
                 "Current node: {:?}; len: {}",
-                &current_node,
+                current_node,
                 current_node.len()
             );
 
             // Try to get matching value for key parts
-            let common_prefix = match current_node.value() {
-                Value::Key(value_key) | Value::Final(value_key, _) => {
-                    #[cfg(feature = "alloc")]
-                    tracing::trace!(
-                        "Comparing value '{}' with our key: '{}'",
-                        String::from_utf8_lossy(&value_key),
-                        String::from_utf8_lossy(&key)
-                    );
-                    find_common_prefix(value_key, key)
-                }
+            let (value_key, is_terminus) = match current_node.value() {
+                Value::Key(value_key) => (value_key, false),
+                Value::Final(value_key, _) => (value_key, true),
                 Value::None => return None,
             };
+            #[cfg(feature = "alloc")]
+            tracing::trace!(
+                "Comparing value '{}' with our key: '{}'",
+                String::from_utf8_lossy(value_key),
+                String::from_utf8_lossy(key)
+            );
+            let common_prefix = find_common_prefix(value_key, key);
             tracing::trace!("Offset :- {:?}", common_prefix);
 
             match common_prefix {
@@ -114,6 +465,12 @@ where
                     current_node = self.node_after(current_node);
                     continue;
                 }
+                // A terminus only matches if it consumes the rest of the key
+                Prefix::Incomplete(_) if is_terminus => {
+                    tracing::trace!("Trying next node");
+                    current_node = self.node_after(current_node);
+                    continue;
+                }
                 // Prefix::PerfectSubset(count) => {
                 Prefix::Incomplete(count) => {
                     key = &key[count..];
@@ -126,75 +483,141 @@ where
                 }
             }
 
-            match (current_node.value(), current_node.next_node.get()) {
+            match (current_node.value(), current_node.next_offset()) {
                 (Value::Final(_, value), OffsetKind::Terminating) => return Some(value),
                 (Value::None, _) => return None,
                 (Value::Key(_), OffsetKind::Offset(success_offset)) => {
-                    let candidate_node = self.node_at(success_offset as usize);
+                    let candidate_node = self.node_at(success_offset);
                     current_node = candidate_node;
                 }
                 _ => unreachable!(),
             }
         }
     }
+
+    /// Looks up `key` like [`get`](Self::get), but checks every node it
+    /// reads, returning an error rather than reading out of bounds if the
+    /// FST is corrupt. This needs no prior validation.
+    pub fn try_get<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<T>, Error> {
+        let mut key = key.as_ref();
+        let mut offset = start_offset::<T, O>();
+
+        // Every step moves forward through the data, so this always ends.
+        loop {
+            let node = self.read_node(offset)?;
+            if let OffsetKind::Empty = node.next {
+                return Ok(None);
+            }
+
+            match find_common_prefix(node.key, key) {
+                Prefix::NoMatch(_) | Prefix::PerfectSubset(_) | Prefix::Divergent(_) => {
+                    offset = node.end;
+                    continue;
+                }
+                Prefix::Incomplete(_) if node.value.is_some() => {
+                    offset = node.end;
+                    continue;
+                }
+                Prefix::Incomplete(count) => key = &key[count..],
+                Prefix::Exact => key = &[],
+            }
+
+            match (node.value, node.next) {
+                (Some(value), _) => return Ok(Some(value)),
+                (None, OffsetKind::Offset(child)) => offset = child,
+                _ => return Err(Error::Corrupt(offset)),
+            }
+        }
+    }
+}
+
+pub(crate) struct Siblings<'a, 'data, T: Integer, O: Offset> {
+    fst: &'a Fst<'data, T, O>,
+    next: Option<Node<'data, T, O>>,
 }
 
-#[repr(C)]
+impl<'data, T, O> Iterator for Siblings<'_, 'data, T, O>
+where
+    T: Integer + Debug,
+    O: Offset,
+{
+    type Item = Node<'data, T, O>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.next.take()?;
+
+        match node.value() {
+            Value::None => None,
+            _ => {
+                self.next = Some(self.fst.node_after(node));
+                Some(node)
+            }
+        }
+    }
+}
+
+/// The size of the header, including reserved bytes after the fields of
+/// [`Header`], which are zeros.
+pub(crate) const HEADER_SIZE: usize = 8;
+
 #[derive(Debug)]
-pub(crate) struct Header {
+struct Header {
     magic_bytes: [u8; 2], // \xff, \xdf
-    version: u8,          // 0
-    alignment: u8,        // ie, are our offsets 2-byte, 4-byte or 8-byte aligned
+    version: u8,          // VERSION
+    offset_width: u8,     // ie, are our offsets 4 or 8 bytes
+    value_width: u8,      // the size of T, from 1 to 16 bytes
 }
 
-#[repr(transparent)]
-#[derive(Debug, Clone, Copy)]
-pub(crate) struct NodeOffset(pub(crate) Option<NonZeroU32>);
-
 #[derive(Debug)]
 pub enum OffsetKind {
     Empty,
-    Offset(u32),
+    Offset(usize),
     Terminating,
 }
 
-impl NodeOffset {
+/// A node of an [`Fst`], read a byte at a time from wherever it lies in the
+/// data. Reading past the end of the data, or a child offset that doesn't
+/// point past its node, panics rather than being undefined or looping
+/// forever, but [`Fst::new_validated`] rules both out beforehand.
+///
+/// Nodes start aligned to both `T` and `O` within the FST, with everything
+/// little-endian:
+///
+/// ```text
+/// [next: O] [value: T, for a terminus] [key length: varint] [key] [padding]
+/// ```
+///
+/// `next` is zero for the empty node ending each group, and `O::TERMINUS`
+/// for a terminus, which holds a value.
+pub struct Node<'data, T, O = u32> {
+    data: &'data [u8],
+    offset: usize,
+    marker: PhantomData<(T, O)>,
+}
+
+impl<T, O> Clone for Node<'_, T, O> {
     #[inline]
-    fn get(self) -> OffsetKind {
-        match self.0 {
-            Some(v) => {
-                if v.get() == u32::MAX {
-                    OffsetKind::Terminating
-                } else {
-                    OffsetKind::Offset(v.get())
-                }
-            }
-            None => OffsetKind::Empty,
-        }
+    fn clone(&self) -> Self {
+        *self
     }
 }
 
-#[repr(C)]
-pub(crate) struct Node<T: Integer> {
-    next_node: NodeOffset, // If null, there are no values in this struct; if max u32, this is a terminus and holds a value
-    raw_value: T,          // There may be more bytes after this, this is simply the minimum size.
-                           // value: [u8],
-}
+impl<T, O> Copy for Node<'_, T, O> {}
 
 #[cfg(feature = "alloc")]
-impl<T: Integer> Debug for Node<T> {
+impl<T: Integer, O: Offset> Debug for Node<'_, T, O> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self.value() {
             Value::None => f.debug_struct("Node::Empty").finish(),
             Value::Final(key, value) => f
                 .debug_struct("Node::Terminus")
                 .field("value", &value)
-                .field("key", &String::from_utf8_lossy(&key))
+                .field("key", &String::from_utf8_lossy(key))
                 .finish(),
             Value::Key(key) => f
                 .debug_struct("Node::Normal")
-                .field("next_node", &self.next_node.get())
-                .field("key", &String::from_utf8_lossy(&key))
+                .field("next_node", &self.next_offset())
+                .field("key", &String::from_utf8_lossy(key))
                 .finish(),
         }
     }
@@ -206,26 +629,57 @@ enum Value<'a, T> {
     Final(&'a [u8], T),
 }
 
-impl<T: Integer> Node<T> {
+impl<'data, T: Integer, O: Offset> Node<'data, T, O> {
     #[inline]
-    fn value(&self) -> Value<'_, T> {
-        match self.next_node.get() {
-            OffsetKind::Offset(_) => {
-                // Get the length (it's a u8)
-                let ptr =
-                    unsafe { (self as *const Node<T> as *const u8).add(size_of::<NodeOffset>()) };
-                let len: u8 = unsafe { *ptr };
-                Value::Key(unsafe { core::slice::from_raw_parts(ptr.add(1), len as usize) })
-            }
+    pub(crate) fn key(&self) -> &'data [u8] {
+        match self.value() {
+            Value::Key(key) | Value::Final(key, _) => key,
+            Value::None => &[],
+        }
+    }
+
+    #[inline]
+    pub(crate) fn terminus(&self) -> Option<T> {
+        match self.value() {
+            Value::Final(_, value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns the key along with the bytes it and its length take up,
+    /// which start after the offset and, for a terminus, the value.
+    #[inline]
+    fn raw_key(&self, start: usize) -> (&'data [u8], usize) {
+        let bytes = &self.data[self.offset + start..];
+        let (len, size) = read_len(bytes).expect("Corrupt key length");
+        (&bytes[size..size + len], size + len)
+    }
+
+    #[inline]
+    fn next_offset(&self) -> OffsetKind {
+        let next = O::read_le_bytes(&self.data[self.offset..self.offset + size_of::<O>()]);
+        if next == O::default() {
+            OffsetKind::Empty
+        } else if next == O::TERMINUS {
+            OffsetKind::Terminating
+        } else {
+            // Children always come after their parent, and following one that
+            // doesn't could go round in circles.
+            let next = next.to_usize();
+            assert!(next > self.offset, "Corrupt child offset {}", next);
+            OffsetKind::Offset(next)
+        }
+    }
+
+    #[inline]
+    fn value(&self) -> Value<'data, T> {
+        match self.next_offset() {
+            OffsetKind::Offset(_) => Value::Key(self.raw_key(size_of::<O>()).0),
             OffsetKind::Terminating => {
-                // Get the length (it's a u8)
-                let ptr = unsafe {
-                    (self as *const Node<T> as *const u8)
-                        .add(size_of::<NodeOffset>() + size_of::<T>())
-                };
-                let len: u8 = unsafe { *ptr };
-                let key = unsafe { core::slice::from_raw_parts(ptr.add(1), len as usize) };
-                Value::Final(key, self.raw_value)
+                let (key, _) = self.raw_key(size_of::<O>() + size_of::<T>());
+                let start = self.offset + size_of::<O>();
+                let value = T::read_le_bytes(&self.data[start..start + size_of::<T>()]);
+                Value::Final(key, value)
             }
             OffsetKind::Empty => Value::None,
         }
@@ -233,26 +687,105 @@ impl<T: Integer> Node<T> {
 
     #[inline]
     fn len(&self) -> usize {
-        match self.next_node.get() {
-            OffsetKind::Offset(_) => {
-                // Get the length (it's a u8)
-                let ptr = unsafe { (self as *const _ as *const u8).add(size_of::<NodeOffset>()) };
-                let len: u8 = unsafe { *ptr };
-                let unaligned = size_of::<NodeOffset>() + size_of::<u8>() + len as usize;
-                let padding = size_of::<T>() - unaligned % size_of::<T>();
-                unaligned + padding
+        let start = match self.next_offset() {
+            OffsetKind::Offset(_) => size_of::<O>(),
+            OffsetKind::Terminating => size_of::<O>() + size_of::<T>(),
+            OffsetKind::Empty => return alignment::<T, O>(),
+        };
+
+        let unaligned = start + self.raw_key(start).1;
+        unaligned + padding::<T, O>(unaligned)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::PathTrie;
+
+    fn data() -> (Vec<&'static str>, Vec<u8>) {
+        let paths = vec!["a/1", "a/2", "a", "b/1/c", "bb", "c/1", "c/2/d/e"];
+        let mut trie = PathTrie::<u32>::new();
+        for (n, path) in paths.iter().enumerate() {
+            trie.insert(path, n as u32);
+        }
+
+        let mut buf = vec![];
+        trie.write_fst(&mut buf).unwrap();
+        (paths, buf)
+    }
+
+    #[test]
+    fn validated() {
+        let (paths, mut buf) = data();
+
+        let fst = Fst::<u32>::new_validated(&buf).unwrap();
+        for path in paths.iter().chain(["a/", "b", "d"].iter()) {
+            assert_eq!(fst.try_get(path).unwrap(), fst.get(path));
+        }
+
+        // Anything after the FST itself is ignored.
+        buf.extend_from_slice(&[0xff; 12]);
+        Fst::<u32>::new_validated(&buf).unwrap();
+        buf.truncate(buf.len() - 12);
+
+        for len in 0..buf.len() {
+            assert!(Fst::<u32>::new_validated(&buf[..len]).is_err());
+        }
+
+        let mut corrupt = buf.clone();
+        for n in HEADER_SIZE..buf.len() {
+            for byte in [0x00, 0x01, 0x04, 0x7f, 0x80, 0xff].iter() {
+                corrupt[n] = *byte;
+
+                let fst = Fst::<u32>::new(&corrupt).unwrap();
+                let valid = Fst::<u32>::new_validated(&corrupt).is_ok();
+                for path in paths.iter() {
+                    let value = fst.try_get(path);
+                    if valid {
+                        assert_eq!(value.unwrap(), fst.get(path));
+                    }
+                }
             }
-            OffsetKind::Terminating => {
-                let ptr = unsafe {
-                    (self as *const _ as *const u8).add(size_of::<NodeOffset>() + size_of::<T>())
-                };
-                let len: u8 = unsafe { *ptr };
-                let unaligned =
-                    size_of::<NodeOffset>() + size_of::<T>() + size_of::<u8>() + len as usize;
-                let padding = size_of::<T>() - unaligned % size_of::<T>();
-                unaligned + padding
+            corrupt[n] = buf[n];
+        }
+    }
+
+    #[test]
+    fn backwards_offset() {
+        let (_, mut buf) = data();
+
+        // Point the first root, "a", back at itself.
+        let start = start_offset::<u32, u32>();
+        buf[start..start + 4].copy_from_slice(&(start as u32).to_le_bytes());
+        assert!(Fst::<u32>::new_validated(&buf).is_err());
+
+        let fst = Fst::<u32>::new(&buf).unwrap();
+        assert!(std::panic::catch_unwind(|| fst.get("a/1")).is_err());
+        assert!(std::panic::catch_unwind(|| fst.children(fst.roots().next().unwrap())).is_err());
+    }
+
+    #[test]
+    fn unaligned() {
+        let mut trie = PathTrie::<u64>::new();
+        for (n, path) in ["a/1", "a/2", "a", "b"].iter().enumerate() {
+            trie.insert(path, u64::MAX - n as u64);
+        }
+
+        let mut buf = vec![];
+        trie.write_fst(&mut buf).unwrap();
+
+        // Embedded at every offset within a container, whatever its alignment.
+        for start in 0..8 {
+            let mut container = vec![0u8; start];
+            container.extend_from_slice(&buf);
+            container.push(0);
+
+            let fst = Fst::<u64>::new_validated(&container[start..]).unwrap();
+            for path in ["a/1", "a/2", "a", "b", "a/3"].iter() {
+                assert_eq!(fst.get(path), trie.get(path));
+                assert_eq!(fst.try_get(path).unwrap(), trie.get(path));
             }
-            OffsetKind::Empty => size_of::<Self>(),
         }
     }
 }
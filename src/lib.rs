@@ -2,26 +2,71 @@
 
 use bare_io::Write;
 use core::{
-    convert::TryFrom,
+    convert::{TryFrom, TryInto},
     fmt::{Debug, Display},
 };
 
+mod aggregate;
+#[cfg(feature = "std")]
+mod concurrent;
+#[cfg(feature = "alloc")]
+mod diff;
+#[cfg(feature = "alloc")]
+mod dot;
 mod fst;
 mod lcp;
+mod multi;
 mod node;
+#[cfg(all(feature = "rayon", feature = "alloc"))]
+mod par;
+#[cfg(feature = "alloc")]
+mod persistent;
+#[cfg(feature = "alloc")]
+mod stats;
+#[cfg(feature = "alloc")]
+mod tree;
 #[cfg(feature = "alloc")]
 mod trie;
 
-pub use fst::Fst;
+pub use aggregate::Aggregate;
+#[cfg(feature = "std")]
+pub use concurrent::ConcurrentPathTrie;
+#[cfg(feature = "alloc")]
+pub use diff::{diff, Change, Diff, Source};
+pub use fst::{Error, Fst, Offset, WriteError};
+#[cfg(feature = "alloc")]
+pub use multi::MultiPathTrie;
+pub use multi::{MultiFst, MultiValues};
+#[cfg(all(feature = "rayon", feature = "alloc"))]
+pub use par::{ParEntries, ParValues};
+#[cfg(feature = "alloc")]
+pub use persistent::PersistentPathTrie;
 #[cfg(feature = "alloc")]
-pub use trie::PathTrie;
+pub use stats::{FstSizes, Stats};
+#[cfg(feature = "alloc")]
+pub use tree::Tree;
+#[cfg(feature = "alloc")]
+pub use trie::{PathTrie, Stream};
+
+/// An iterator whose items may borrow from the iterator itself, such as a
+/// key held in a buffer that is reused for every item.
+pub trait Streamer<'a> {
+    type Item: 'a;
+
+    fn next(&'a mut self) -> Option<Self::Item>;
+}
 
 #[derive(Debug)]
 #[repr(transparent)]
 struct ByteKey([u8]);
 
-pub trait Integer: Default + Display + Debug + Copy + sealed::Sealed + TryFrom<u64> {
+pub trait Integer:
+    Default + Display + Debug + Copy + PartialEq + sealed::Sealed + TryFrom<u64>
+{
     fn write_le_bytes<W: Write>(self, writer: &mut W) -> Result<(), bare_io::Error>;
+
+    /// Reads a value from exactly `size_of::<Self>()` little-endian bytes.
+    fn read_le_bytes(bytes: &[u8]) -> Self;
 }
 mod sealed {
     pub trait Sealed {}
@@ -37,6 +82,11 @@ impl Integer for u128 {
     fn write_le_bytes<W: Write>(self, writer: &mut W) -> Result<(), bare_io::Error> {
         writer.write_all(&self.to_le_bytes())
     }
+
+    #[inline]
+    fn read_le_bytes(bytes: &[u8]) -> Self {
+        Self::from_le_bytes(bytes.try_into().expect("wrong number of bytes for integer"))
+    }
 }
 
 impl Integer for u64 {
@@ -44,6 +94,11 @@ impl Integer for u64 {
     fn write_le_bytes<W: Write>(self, writer: &mut W) -> Result<(), bare_io::Error> {
         writer.write_all(&self.to_le_bytes())
     }
+
+    #[inline]
+    fn read_le_bytes(bytes: &[u8]) -> Self {
+        Self::from_le_bytes(bytes.try_into().expect("wrong number of bytes for integer"))
+    }
 }
 
 impl Integer for u32 {
@@ -51,16 +106,31 @@ impl Integer for u32 {
     fn write_le_bytes<W: Write>(self, writer: &mut W) -> Result<(), bare_io::Error> {
         writer.write_all(&self.to_le_bytes())
     }
+
+    #[inline]
+    fn read_le_bytes(bytes: &[u8]) -> Self {
+        Self::from_le_bytes(bytes.try_into().expect("wrong number of bytes for integer"))
+    }
 }
 impl Integer for u16 {
     #[inline]
     fn write_le_bytes<W: Write>(self, writer: &mut W) -> Result<(), bare_io::Error> {
         writer.write_all(&self.to_le_bytes())
     }
+
+    #[inline]
+    fn read_le_bytes(bytes: &[u8]) -> Self {
+        Self::from_le_bytes(bytes.try_into().expect("wrong number of bytes for integer"))
+    }
 }
 impl Integer for u8 {
     #[inline]
     fn write_le_bytes<W: Write>(self, writer: &mut W) -> Result<(), bare_io::Error> {
         writer.write_all(&self.to_le_bytes())
     }
+
+    #[inline]
+    fn read_le_bytes(bytes: &[u8]) -> Self {
+        Self::from_le_bytes(bytes.try_into().expect("wrong number of bytes for integer"))
+    }
 }
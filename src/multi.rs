@@ -0,0 +1,348 @@
+use core::{
+    convert::{TryFrom, TryInto},
+    marker::PhantomData,
+    mem::size_of,
+};
+
+#[cfg(feature = "alloc")]
+use crate::WriteError;
+use crate::{fst::Error, Fst, Integer};
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(feature = "alloc")]
+use crate::PathTrie;
+#[cfg(feature = "alloc")]
+use alloc::{boxed::Box, vec, vec::Vec};
+#[cfg(feature = "alloc")]
+use bare_io::Write;
+
+#[inline]
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+/// A [`PathTrie`] that keeps a list of values for each key.
+#[cfg(feature = "alloc")]
+#[derive(Debug, Default)]
+pub struct MultiPathTrie<T: Integer> {
+    /// Maps each key to its slot in `lists`.
+    index: PathTrie<u32>,
+    lists: Vec<Vec<T>>,
+    /// Slots in `lists` no longer used by any key.
+    free: Vec<u32>,
+}
+
+#[cfg(feature = "alloc")]
+impl<T: Integer> MultiPathTrie<T> {
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            index: PathTrie::new(),
+            lists: vec![],
+            free: vec![],
+        }
+    }
+
+    /// Returns the number of keys, not counting repeated values.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    #[inline]
+    pub fn keys<'a>(&'a self) -> impl Iterator<Item = Box<[u8]>> + 'a {
+        self.index.keys()
+    }
+
+    #[inline]
+    pub fn entries(&self) -> impl Iterator<Item = (Box<[u8]>, &[T])> {
+        let lists = &self.lists;
+        self.index
+            .entries()
+            .map(move |(key, slot)| (key, &*lists[*slot as usize]))
+    }
+
+    /// Adds `value` to the end of the values for `key`.
+    pub fn insert_multi<K: AsRef<[u8]>>(&mut self, key: K, value: T) {
+        let key = key.as_ref();
+
+        if let Some(slot) = self.index.get(key) {
+            return self.lists[slot as usize].push(value);
+        }
+
+        let slot = match self.free.pop() {
+            Some(slot) => {
+                self.lists[slot as usize].push(value);
+                slot
+            }
+            None => {
+                let slot = u32::try_from(self.lists.len())
+                    .expect("MultiPathTries are limited to 2^32 keys");
+                self.lists.push(vec![value]);
+                slot
+            }
+        };
+
+        self.index.insert(key, slot);
+    }
+
+    /// Returns every value for `key` in the order they were inserted.
+    #[inline]
+    pub fn get_all<K: AsRef<[u8]>>(&self, key: K) -> &[T] {
+        match self.index.get(key) {
+            Some(slot) => &self.lists[slot as usize],
+            None => &[],
+        }
+    }
+
+    /// Removes the first occurrence of `value` for `key`, and the key itself
+    /// along with its last value. Returns whether anything was removed.
+    pub fn remove_one<K: AsRef<[u8]>>(&mut self, key: K, value: T) -> bool {
+        let key = key.as_ref();
+        let slot = match self.index.get(key) {
+            Some(slot) => slot,
+            None => return false,
+        };
+
+        let list = &mut self.lists[slot as usize];
+        match list.iter().position(|x| *x == value) {
+            Some(index) => list.remove(index),
+            None => return false,
+        };
+
+        if list.is_empty() {
+            self.index.remove(key);
+            self.free.push(slot);
+        }
+
+        true
+    }
+
+    /// Writes an ordinary [`Fst`] of `u32`s, numbering each key's list of
+    /// values in key order, followed by the lists themselves:
+    ///
+    /// ```text
+    /// [index FST]
+    /// [list count: u32] [list starts: u32 × (count + 1)] [values: T × total]
+    /// [value width in bytes: u32] [offset of the list count: u32]
+    /// ```
+    ///
+    /// Everything after the index is little-endian, and list starts count
+    /// values rather than bytes.
+    pub fn write_fst<W: Write>(&self, writer: &mut W) -> Result<(), WriteError> {
+        let overflow = |offset: u64| WriteError::OffsetOverflow {
+            offset,
+            width: size_of::<u32>(),
+        };
+
+        let lists = self
+            .index
+            .values()
+            .map(|slot| &self.lists[*slot as usize])
+            .collect::<Vec<_>>();
+
+        // Checked before anything is written, so nothing is left half done.
+        let count = u32::try_from(lists.len()).map_err(|_| overflow(lists.len() as u64))?;
+        let mut starts = Vec::with_capacity(lists.len() + 1);
+        starts.push(0u32);
+        for list in lists.iter() {
+            let start = starts[starts.len() - 1];
+            let end = u32::try_from(list.len())
+                .ok()
+                .and_then(|len| start.checked_add(len))
+                .ok_or_else(|| overflow(start as u64 + list.len() as u64))?;
+            starts.push(end);
+        }
+
+        // Lists are numbered in key order rather than by slot.
+        let index = PathTrie::<u32>::from_sorted_iter(self.index.keys().zip(0u32..));
+        let offset = index.fst_size();
+        let offset = u32::try_from(offset).map_err(|_| overflow(offset as u64))?;
+        index.write_fst(writer)?;
+
+        count.write_le_bytes(writer)?;
+        for start in starts {
+            start.write_le_bytes(writer)?;
+        }
+
+        for value in lists.iter().flat_map(|list| list.iter()) {
+            value.write_le_bytes(writer)?;
+        }
+
+        (size_of::<T>() as u32).write_le_bytes(writer)?;
+        offset.write_le_bytes(writer)?;
+        Ok(writer.flush()?)
+    }
+}
+
+/// Reads the FST written by [`MultiPathTrie::write_fst`](crate::MultiPathTrie::write_fst).
+#[derive(Debug)]
+pub struct MultiFst<'data, T> {
+    index: Fst<'data, u32>,
+    count: usize,
+    starts: &'data [u8],
+    values: &'data [u8],
+    marker: PhantomData<T>,
+}
+
+impl<'data, T: Integer> MultiFst<'data, T> {
+    pub fn new(data: &'data [u8]) -> Result<MultiFst<'data, T>, Error> {
+        let index = Fst::new(data)?;
+
+        let trailer = data.len().checked_sub(8).ok_or(Error::TooSmall)?;
+        let width = read_u32(data, trailer).ok_or(Error::TooSmall)?;
+        if width != size_of::<T>() as u32 {
+            let width = u8::try_from(width).unwrap_or(u8::MAX);
+            return Err(Error::InvalidValueWidth(width, size_of::<T>()));
+        }
+
+        let offset = read_u32(data, trailer + 4).ok_or(Error::TooSmall)? as usize;
+        let lists = data.get(offset..trailer).ok_or(Error::TooSmall)?;
+
+        let count = read_u32(lists, 0).ok_or(Error::TooSmall)? as usize;
+        let starts_len = count
+            .checked_add(1)
+            .and_then(|x| x.checked_mul(4))
+            .ok_or(Error::TooSmall)?;
+        let starts = lists.get(4..4 + starts_len).ok_or(Error::TooSmall)?;
+
+        let total = read_u32(starts, count * 4).ok_or(Error::TooSmall)? as usize;
+        let values = lists
+            .get(4 + starts_len..)
+            .filter(|values| values.len() / size_of::<T>() >= total)
+            .ok_or(Error::TooSmall)?;
+
+        Ok(MultiFst {
+            index,
+            count,
+            starts,
+            values,
+            marker: PhantomData,
+        })
+    }
+
+    /// Returns every value for `key`, which is none if it isn't present.
+    pub fn get_all<K: AsRef<[u8]>>(&self, key: K) -> MultiValues<'data, T> {
+        let list = self
+            .index
+            .get(key)
+            .map(|list| list as usize)
+            .filter(|list| *list < self.count)
+            .and_then(|list| {
+                let start = read_u32(self.starts, list * 4)? as usize;
+                let end = read_u32(self.starts, list * 4 + 4)? as usize;
+                self.values
+                    .get(start * size_of::<T>()..end * size_of::<T>())
+            })
+            .unwrap_or(&[]);
+
+        MultiValues {
+            data: list,
+            marker: PhantomData,
+        }
+    }
+}
+
+/// The values for one key of a [`MultiFst`].
+#[derive(Debug, Clone)]
+pub struct MultiValues<'data, T> {
+    data: &'data [u8],
+    marker: PhantomData<T>,
+}
+
+impl<T: Integer> Iterator for MultiValues<'_, T> {
+    type Item = T;
+
+    #[inline]
+    fn next(&mut self) -> Option<T> {
+        if self.data.len() < size_of::<T>() {
+            return None;
+        }
+
+        let (value, rest) = self.data.split_at(size_of::<T>());
+        self.data = rest;
+        Some(T::read_le_bytes(value))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.data.len() / size_of::<T>();
+        (len, Some(len))
+    }
+}
+
+impl<T: Integer> ExactSizeIterator for MultiValues<'_, T> {}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use memmap::Mmap;
+    use std::fs::File;
+    use std::io::Cursor;
+
+    #[test]
+    fn insert_and_remove() {
+        let mut trie = MultiPathTrie::<u64>::new();
+
+        trie.insert_multi("src/lib.rs", 1);
+        trie.insert_multi("src/lib.rs", 2);
+        trie.insert_multi("src", 3);
+        trie.insert_multi("src/lib.rs", 1);
+
+        assert_eq!(trie.get_all("src/lib.rs"), &[1, 2, 1]);
+        assert_eq!(trie.get_all("src"), &[3]);
+        assert_eq!(trie.get_all("src/"), &[]);
+        assert_eq!(trie.len(), 2);
+
+        assert!(trie.remove_one("src/lib.rs", 1));
+        assert!(!trie.remove_one("src/lib.rs", 3));
+        assert_eq!(trie.get_all("src/lib.rs"), &[2, 1]);
+
+        assert!(trie.remove_one("src", 3));
+        assert_eq!(trie.len(), 1);
+        assert_eq!(trie.keys().count(), 1);
+
+        // The freed list is picked up again.
+        trie.insert_multi("a", 4);
+        assert_eq!(trie.lists.len(), 2);
+        assert_eq!(trie.get_all("a"), &[4]);
+    }
+
+    #[test]
+    fn fst() {
+        let mut trie = MultiPathTrie::<u64>::new();
+        let paths = ["c/1", "a/1", "a/2", "b", "a"];
+
+        for (n, path) in paths.iter().enumerate() {
+            for value in 0..n as u64 {
+                trie.insert_multi(path, value * 1_000_000_000_000);
+            }
+        }
+        trie.insert_multi("d", u64::MAX);
+
+        let mut buf = Cursor::new(vec![]);
+        trie.write_fst(&mut buf).unwrap();
+
+        std::fs::write("./test-multi.fst", buf.into_inner()).unwrap();
+        let mmap = unsafe { Mmap::map(&File::open("./test-multi.fst").unwrap()).unwrap() };
+        let fst = MultiFst::<u64>::new(&mmap).unwrap();
+
+        for path in paths.iter().chain(["d", "e", "a/"].iter()) {
+            let values = fst.get_all(path);
+            assert_eq!(values.len(), trie.get_all(path).len());
+            assert_eq!(values.collect::<Vec<_>>(), trie.get_all(path));
+        }
+
+        assert!(matches!(
+            MultiFst::<u32>::new(&mmap),
+            Err(Error::InvalidValueWidth(8, 4))
+        ));
+    }
+}
@@ -1,6 +1,7 @@
 use crate::Integer;
 use core::{
     cmp::Ordering,
+    convert::TryFrom,
     fmt::{self, Debug, Display, Write},
 };
 use indenter::indented;
@@ -10,14 +11,420 @@ extern crate alloc;
 #[cfg(feature = "alloc")]
 use alloc::{boxed::Box, vec, vec::Vec};
 
+pub(crate) type NodeId = u32;
+
+pub(crate) const ROOT: NodeId = 0;
+
 #[derive(Debug)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[cfg(feature = "alloc")]
 pub enum NodeBody<T: Integer> {
-    Children(Vec<Node<T>>),
+    Children(Children),
     Value(T),
 }
 
+/// The children of a node, indexed by the first byte of their key.
+///
+/// Siblings never share a first byte, so there are at most 256 of these plus
+/// the child with the empty key, which holds the value of the node itself.
+/// Children are kept in key order, with the empty key first.
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg(feature = "alloc")]
+pub struct Children {
+    end: Option<NodeId>,
+    layout: Layout,
+    /// The number of values below this node.
+    count: u32,
+}
+
+/// The root is never anyone's child, so its id marks an empty slot.
+const EMPTY: NodeId = ROOT;
+
+/// Child layouts in the style of an adaptive radix tree, growing with fanout.
+///
+/// Small nodes keep sorted arrays that are scanned directly, while larger
+/// ones index by byte, either through a table of slots or directly.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg(feature = "alloc")]
+enum Layout {
+    Node4 {
+        len: u8,
+        bytes: [u8; 4],
+        ids: [NodeId; 4],
+    },
+    Node16(Box<Node16>),
+    Node48(Box<Node48>),
+    Node256 {
+        len: u16,
+        ids: Box<[NodeId]>,
+    },
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg(feature = "alloc")]
+struct Node16 {
+    len: u8,
+    bytes: [u8; 16],
+    ids: [NodeId; 16],
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg(feature = "alloc")]
+struct Node48 {
+    len: u8,
+    // One past the slot in `ids` for each byte, or zero.
+    #[cfg_attr(feature = "serde", serde(with = "big_array"))]
+    index: [u8; 256],
+    #[cfg_attr(feature = "serde", serde(with = "big_array"))]
+    ids: [NodeId; 48],
+}
+
+/// Serde only handles arrays of up to 32 items by itself.
+#[cfg(all(feature = "serde", feature = "alloc"))]
+mod big_array {
+    use super::Vec;
+    use core::convert::TryInto;
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+    pub(super) fn serialize<S, T, const N: usize>(
+        array: &[T; N],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        T: Serialize,
+    {
+        serializer.collect_seq(array.iter())
+    }
+
+    pub(super) fn deserialize<'de, D, T, const N: usize>(
+        deserializer: D,
+    ) -> Result<[T; N], D::Error>
+    where
+        D: Deserializer<'de>,
+        T: Deserialize<'de>,
+    {
+        let items = Vec::<T>::deserialize(deserializer)?;
+        let len = items.len();
+        items
+            .try_into()
+            .map_err(|_| D::Error::invalid_length(len, &"an array of the layout's size"))
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Default for Layout {
+    fn default() -> Self {
+        Layout::Node4 {
+            len: 0,
+            bytes: [0; 4],
+            ids: [EMPTY; 4],
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+#[inline]
+fn sorted_insert(len: &mut u8, bytes: &mut [u8], ids: &mut [NodeId], byte: u8, id: NodeId) {
+    let end = *len as usize;
+    let index = bytes[..end].iter().position(|x| *x > byte).unwrap_or(end);
+    debug_assert!(
+        index == 0 || bytes[index - 1] != byte,
+        "siblings share a first byte"
+    );
+
+    bytes.copy_within(index..end, index + 1);
+    ids.copy_within(index..end, index + 1);
+    bytes[index] = byte;
+    ids[index] = id;
+    *len += 1;
+}
+
+#[cfg(feature = "alloc")]
+#[inline]
+fn sorted_remove(len: &mut u8, bytes: &mut [u8], ids: &mut [NodeId], byte: u8) {
+    let end = *len as usize;
+    let index = bytes[..end]
+        .iter()
+        .position(|x| *x == byte)
+        .expect("no child with this first byte");
+
+    bytes.copy_within(index + 1..end, index);
+    ids.copy_within(index + 1..end, index);
+    ids[end - 1] = EMPTY;
+    *len -= 1;
+}
+
+#[cfg(feature = "alloc")]
+impl Layout {
+    #[inline]
+    fn len(&self) -> usize {
+        match self {
+            Layout::Node4 { len, .. } => *len as usize,
+            Layout::Node16(node) => node.len as usize,
+            Layout::Node48(node) => node.len as usize,
+            Layout::Node256 { len, .. } => *len as usize,
+        }
+    }
+
+    #[inline]
+    fn find(&self, byte: u8) -> Option<NodeId> {
+        let id = match self {
+            Layout::Node4 { len, bytes, ids } => bytes[..*len as usize]
+                .iter()
+                .position(|x| *x == byte)
+                .map(|i| ids[i])?,
+            Layout::Node16(node) => node.bytes[..node.len as usize]
+                .iter()
+                .position(|x| *x == byte)
+                .map(|i| node.ids[i])?,
+            Layout::Node48(node) => match node.index[byte as usize] {
+                0 => return None,
+                slot => node.ids[slot as usize - 1],
+            },
+            Layout::Node256 { ids, .. } => ids[byte as usize],
+        };
+
+        Some(id).filter(|id| *id != EMPTY)
+    }
+
+    /// Returns the first child at or after position `pos`, along with the
+    /// position to continue from.
+    #[inline]
+    fn next_from(&self, pos: usize) -> Option<(usize, u8, NodeId)> {
+        match self {
+            Layout::Node4 { len, bytes, ids } if pos < *len as usize => {
+                Some((pos + 1, bytes[pos], ids[pos]))
+            }
+            Layout::Node16(node) if pos < node.len as usize => {
+                Some((pos + 1, node.bytes[pos], node.ids[pos]))
+            }
+            Layout::Node4 { .. } | Layout::Node16(_) => None,
+            Layout::Node48(node) => node
+                .index
+                .iter()
+                .enumerate()
+                .skip(pos)
+                .find(|(_, slot)| **slot != 0)
+                .map(|(byte, slot)| (byte + 1, byte as u8, node.ids[*slot as usize - 1])),
+            Layout::Node256 { ids, .. } => ids
+                .iter()
+                .enumerate()
+                .skip(pos)
+                .find(|(_, id)| **id != EMPTY)
+                .map(|(byte, id)| (byte + 1, byte as u8, *id)),
+        }
+    }
+
+    #[inline]
+    fn iter(&self) -> impl Iterator<Item = (u8, NodeId)> + '_ {
+        let mut pos = 0;
+        core::iter::from_fn(move || {
+            let (next, byte, id) = self.next_from(pos)?;
+            pos = next;
+            Some((byte, id))
+        })
+    }
+
+    fn insert(&mut self, byte: u8, id: NodeId) {
+        debug_assert!(self.find(byte).is_none(), "siblings share a first byte");
+
+        match self {
+            Layout::Node4 { len, bytes, ids } if *len < 4 => {
+                sorted_insert(len, bytes, ids, byte, id);
+            }
+            Layout::Node16(node) if node.len < 16 => {
+                let Node16 { len, bytes, ids } = &mut **node;
+                sorted_insert(len, bytes, ids, byte, id);
+            }
+            Layout::Node48(node) if node.len < 48 => {
+                node.ids[node.len as usize] = id;
+                node.len += 1;
+                node.index[byte as usize] = node.len;
+            }
+            Layout::Node256 { len, ids } => {
+                ids[byte as usize] = id;
+                *len += 1;
+            }
+            _ => {
+                self.grow();
+                self.insert(byte, id);
+            }
+        }
+    }
+
+    fn remove(&mut self, byte: u8) {
+        match self {
+            Layout::Node4 { len, bytes, ids } => sorted_remove(len, bytes, ids, byte),
+            Layout::Node16(node) => {
+                let Node16 { len, bytes, ids } = &mut **node;
+                sorted_remove(len, bytes, ids, byte);
+            }
+            Layout::Node48(node) => {
+                let Node48 { len, index, ids } = &mut **node;
+                let slot = index[byte as usize] as usize;
+                debug_assert!(slot != 0, "no child with this first byte");
+                index[byte as usize] = 0;
+
+                // The last child moves into the freed slot.
+                let last = *len as usize;
+                ids[slot - 1] = ids[last - 1];
+                ids[last - 1] = EMPTY;
+                *len -= 1;
+                if slot < last {
+                    let moved = index.iter().position(|x| *x as usize == last).unwrap();
+                    index[moved] = slot as u8;
+                }
+            }
+            Layout::Node256 { len, ids } => {
+                debug_assert!(ids[byte as usize] != EMPTY, "no child with this first byte");
+                ids[byte as usize] = EMPTY;
+                *len -= 1;
+            }
+        }
+
+        // Shrink well below the size we grew at, so that a node that sees
+        // inserts and removes in turn doesn't keep changing its layout.
+        let shrink = match self {
+            Layout::Node4 { .. } => false,
+            Layout::Node16(node) => node.len <= 3,
+            Layout::Node48(node) => node.len <= 12,
+            Layout::Node256 { len, .. } => *len <= 36,
+        };
+
+        if shrink {
+            let mut shrunk = Layout::default();
+            for (byte, id) in self.iter() {
+                shrunk.insert(byte, id);
+            }
+            *self = shrunk;
+        }
+    }
+
+    fn grow(&mut self) {
+        let len = self.len();
+        let children = self.iter();
+
+        let grown = match self {
+            Layout::Node4 { .. } => {
+                let mut node = Node16 {
+                    len: 0,
+                    bytes: [0; 16],
+                    ids: [EMPTY; 16],
+                };
+                for (n, (byte, id)) in children.enumerate() {
+                    node.bytes[n] = byte;
+                    node.ids[n] = id;
+                }
+                node.len = len as u8;
+                Layout::Node16(Box::new(node))
+            }
+            Layout::Node16(_) => {
+                let mut node = Node48 {
+                    len: 0,
+                    index: [0; 256],
+                    ids: [EMPTY; 48],
+                };
+                for (byte, id) in children {
+                    node.ids[node.len as usize] = id;
+                    node.len += 1;
+                    node.index[byte as usize] = node.len;
+                }
+                Layout::Node48(Box::new(node))
+            }
+            Layout::Node48(_) => {
+                let mut ids = vec![EMPTY; 256].into_boxed_slice();
+                for (byte, id) in children {
+                    ids[byte as usize] = id;
+                }
+                Layout::Node256 {
+                    len: len as u16,
+                    ids,
+                }
+            }
+            Layout::Node256 { .. } => unreachable!(),
+        };
+
+        *self = grown;
+    }
+
+    #[cfg(feature = "rayon")]
+    /// Offsets every child id by `by`, for when a whole arena is moved.
+    fn shift(&mut self, by: NodeId) {
+        let ids = match self {
+            Layout::Node4 { len, ids, .. } => &mut ids[..*len as usize],
+            Layout::Node16(node) => {
+                let len = node.len as usize;
+                &mut node.ids[..len]
+            }
+            Layout::Node48(node) => {
+                let len = node.len as usize;
+                &mut node.ids[..len]
+            }
+            Layout::Node256 { ids, .. } => &mut ids[..],
+        };
+
+        for id in ids.iter_mut().filter(|id| **id != EMPTY) {
+            *id += by;
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Children {
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        self.layout.len() + self.end.is_some() as usize
+    }
+
+    #[inline]
+    pub(crate) fn end(&self) -> Option<NodeId> {
+        self.end
+    }
+
+    #[inline]
+    pub(crate) fn find(&self, byte: u8) -> Option<NodeId> {
+        self.layout.find(byte)
+    }
+
+    #[inline]
+    pub(crate) fn iter(&self) -> impl Iterator<Item = NodeId> + '_ {
+        self.end.into_iter().chain(self.layout.iter().map(|x| x.1))
+    }
+
+    #[cfg(feature = "rayon")]
+    fn shift(&mut self, by: NodeId) {
+        if let Some(end) = &mut self.end {
+            *end += by;
+        }
+        self.layout.shift(by);
+    }
+
+    pub(crate) fn insert(&mut self, first: Option<u8>, id: NodeId) {
+        match first {
+            None => {
+                debug_assert!(self.end.is_none());
+                self.end = Some(id);
+            }
+            Some(byte) => self.layout.insert(byte, id),
+        }
+    }
+
+    pub(crate) fn remove(&mut self, first: Option<u8>) {
+        match first {
+            None => {
+                debug_assert!(self.end.is_some());
+                self.end = None;
+            }
+            Some(byte) => self.layout.remove(byte),
+        }
+    }
+}
+
 #[cfg(feature = "alloc")]
 impl<T: Integer> NodeBody<T> {
     pub(crate) fn assert_value(&self) -> T {
@@ -28,145 +435,299 @@ impl<T: Integer> NodeBody<T> {
     }
 }
 
+#[derive(Debug)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[cfg(feature = "alloc")]
 pub struct Node<T: Integer> {
-    pub(crate) key: Box<[u8]>,
+    pub(crate) key_start: u32,
+    pub(crate) key_len: u32,
     pub(crate) body: NodeBody<T>,
 }
 
+/// Backing storage for the nodes of a trie.
+///
+/// Every node lives in one vector and refers to its children by index, and
+/// every key is a range of one shared byte buffer. Splitting a key only
+/// shrinks ranges, so bytes are only ever copied in when a key is inserted.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[cfg(feature = "alloc")]
-impl<T: Integer> Debug for Node<T> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut x = f.debug_struct("Node");
+pub struct Arena<T: Integer> {
+    nodes: Vec<Node<T>>,
+    keys: Vec<u8>,
+}
 
-        match core::str::from_utf8(&self.key) {
-            Ok(v) => x.field("key", &v),
-            Err(_) => x.field("key", &self.key),
-        };
-        x.field("body", &self.body).finish()
+#[cfg(feature = "alloc")]
+impl<T: Integer> Default for Arena<T> {
+    fn default() -> Self {
+        Arena::new()
     }
 }
 
 #[cfg(feature = "alloc")]
-impl<T: Integer> Display for Node<T> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match core::str::from_utf8(&self.key) {
-            Ok(v) => f.write_fmt(format_args!("{:?}", v)),
-            Err(_) => f.write_fmt(format_args!("{:?}", &self.key)),
-        }?;
+impl<T: Integer> Arena<T> {
+    pub(crate) fn new() -> Self {
+        Arena {
+            nodes: vec![Node {
+                key_start: 0,
+                key_len: 0,
+                body: NodeBody::Children(Children::default()),
+            }],
+            keys: vec![],
+        }
+    }
 
-        f.write_str(" => ")?;
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        self.nodes.len()
+    }
 
-        match &self.body {
-            NodeBody::Children(children) => {
-                f.write_str("[")?;
-                for child in children.iter() {
-                    writeln!(f)?;
-                    let mut indent =
-                        indented(f).with_format(indenter::Format::Uniform { indentation: "  " });
-                    write!(indent, "{}", child)?
-                }
-                f.write_str("\n],")?;
-            }
-            NodeBody::Value(value) => {
-                f.write_fmt(format_args!("{},", value))?;
-            }
+    #[cfg(test)]
+    pub(crate) fn key_bytes(&self) -> usize {
+        self.keys.len()
+    }
+
+    #[inline]
+    pub(crate) fn key(&self, id: NodeId) -> &[u8] {
+        let node = &self.nodes[id as usize];
+        let start = node.key_start as usize;
+        &self.keys[start..start + node.key_len as usize]
+    }
+
+    #[inline]
+    pub(crate) fn body(&self, id: NodeId) -> &NodeBody<T> {
+        &self.nodes[id as usize].body
+    }
+
+    #[inline]
+    pub(crate) fn children(&self, id: NodeId) -> Option<&Children> {
+        match self.body(id) {
+            NodeBody::Children(children) => Some(children),
+            NodeBody::Value(_) => None,
+        }
+    }
+
+    /// Returns the number of values at or below `id`.
+    #[inline]
+    pub(crate) fn count(&self, id: NodeId) -> u32 {
+        match self.body(id) {
+            NodeBody::Children(children) => children.count,
+            NodeBody::Value(_) => 1,
+        }
+    }
+
+    /// Updates the count of `id` from the counts of its children.
+    pub(crate) fn recount(&mut self, id: NodeId) {
+        let count = match self.children(id) {
+            Some(children) => children.iter().map(|child| self.count(child)).sum(),
+            None => return,
         };
 
-        Ok(())
+        if let NodeBody::Children(children) = &mut self.nodes[id as usize].body {
+            children.count = count;
+        }
     }
-}
 
-#[cfg(feature = "alloc")]
-impl<T: Integer> Node<T> {
-    pub(crate) fn diverge(&mut self, index: usize, partial: usize, key: &[u8], value: T) {
-        let subnode = self.get_mut(index);
+    #[inline]
+    pub(crate) fn node_ref(&self, id: NodeId) -> NodeRef<'_, T> {
+        NodeRef { arena: self, id }
+    }
 
-        let key_prefix = key[..partial].to_vec().into_boxed_slice();
-        let skey = subnode.key[partial..].to_vec().into_boxed_slice();
-        match &mut subnode.body {
-            NodeBody::Children(children) => {
-                let mut swap = Vec::new();
-                core::mem::swap(&mut swap, children);
-                subnode.push(Node {
-                    key: skey,
-                    body: NodeBody::Children(swap),
-                });
+    fn alloc_raw(&mut self, key_start: u32, key_len: u32, body: NodeBody<T>) -> NodeId {
+        let id = NodeId::try_from(self.nodes.len()).expect("Tries are limited to 2^32 nodes");
+        self.nodes.push(Node {
+            key_start,
+            key_len,
+            body,
+        });
+        id
+    }
+
+    pub(crate) fn alloc(&mut self, key: &[u8], body: NodeBody<T>) -> NodeId {
+        let start = self.alloc_key(key);
+        self.alloc_raw(start, key.len() as u32, body)
+    }
+
+    fn alloc_key(&mut self, key: &[u8]) -> u32 {
+        let start = u32::try_from(self.keys.len())
+            .ok()
+            .filter(|start| start.checked_add(key.len() as u32).is_some())
+            .expect("Tries are limited to 4 GiB of key bytes");
+        self.keys.extend_from_slice(key);
+        start
+    }
+
+    #[cfg(feature = "rayon")]
+    /// Moves every node of `other` into this arena, adding the children of its
+    /// root to `parent`. None of them may share a first byte with a child that
+    /// `parent` already has.
+    pub(crate) fn graft(&mut self, parent: NodeId, other: Arena<T>) {
+        let by = u32::try_from(self.nodes.len() + other.nodes.len())
+            .map(|_| self.nodes.len() as NodeId)
+            .expect("Tries are limited to 2^32 nodes");
+        let key_by = u32::try_from(self.keys.len() + other.keys.len())
+            .map(|_| self.keys.len() as u32)
+            .expect("Tries are limited to 4 GiB of key bytes");
+
+        self.keys.extend_from_slice(&other.keys);
+        self.nodes.extend(other.nodes.into_iter().map(|mut node| {
+            node.key_start += key_by;
+            if let NodeBody::Children(children) = &mut node.body {
+                children.shift(by);
             }
-            NodeBody::Value(_) => {
-                subnode.convert_value_to_children(skey);
+            node
+        }));
+
+        // Their root is left behind empty, as nothing points to it.
+        if let NodeBody::Children(children) = self.take_body(by + ROOT) {
+            for child in children.iter() {
+                self.push(parent, child);
             }
         }
+    }
 
-        subnode.push(Node {
-            key: key[partial..].to_vec().into_boxed_slice(),
-            body: NodeBody::Value(value),
-        });
+    fn take_body(&mut self, id: NodeId) -> NodeBody<T> {
+        core::mem::replace(
+            &mut self.nodes[id as usize].body,
+            NodeBody::Children(Children::default()),
+        )
+    }
 
-        subnode.key = key_prefix;
+    pub(crate) fn diverge(&mut self, subnode: NodeId, partial: usize, key: &[u8], value: T) {
+        let (start, len) = {
+            let node = &self.nodes[subnode as usize];
+            (node.key_start, node.key_len)
+        };
 
-        match &mut self.body {
-            NodeBody::Children(children) => {
-                children.sort_unstable();
+        match self.body(subnode) {
+            NodeBody::Children(_) => {
+                let body = self.take_body(subnode);
+                let child = self.alloc_raw(start + partial as u32, len - partial as u32, body);
+                self.push(subnode, child);
+            }
+            NodeBody::Value(_) => {
+                self.convert_value_to_children(subnode, len as usize - partial);
             }
-            NodeBody::Value(_) => unreachable!(),
         }
+
+        let child = self.alloc(&key[partial..], NodeBody::Value(value));
+        self.push(subnode, child);
+
+        // The new prefix is already the start of the existing key, so the
+        // node keeps its first byte and its place among its siblings.
+        self.nodes[subnode as usize].key_len = partial as u32;
     }
 
-    pub(crate) fn convert_value_to_children(&mut self, key: Box<[u8]>) {
+    pub(crate) fn convert_value_to_children(&mut self, id: NodeId, suffix_len: usize) {
         debug_assert!(
-            core::mem::discriminant(&self.body)
+            core::mem::discriminant(self.body(id))
                 == core::mem::discriminant(&NodeBody::Value(T::default()))
         );
 
-        let mut body = NodeBody::Children(vec![]);
-        core::mem::swap(&mut body, &mut self.body);
-        self.key = self.key[..self.key.len() - key.len()]
-            .to_vec()
-            .into_boxed_slice();
-        self.push(Node { key, body });
+        let body = self.take_body(id);
+        let node = &mut self.nodes[id as usize];
+        node.key_len -= suffix_len as u32;
+        let start = node.key_start + node.key_len;
+        let child = self.alloc_raw(start, suffix_len as u32, body);
+        self.push(id, child);
     }
 
-    pub fn insert(&mut self, index: usize, value: Node<T>) {
-        match &mut self.body {
-            NodeBody::Children(children) => {
-                children.insert(index, value);
-            }
-            NodeBody::Value(_) => {
-                self.convert_value_to_children(Default::default());
-                self.push(value);
-            }
-        };
+    pub(crate) fn split(&mut self, id: NodeId, at: usize) {
+        debug_assert!(at < self.key(id).len());
+
+        let body = self.take_body(id);
+        let node = &mut self.nodes[id as usize];
+        let (start, len) = (node.key_start + at as u32, node.key_len - at as u32);
+        node.key_len = at as u32;
+        let child = self.alloc_raw(start, len, body);
+        self.push(id, child);
     }
 
-    pub fn push(&mut self, value: Node<T>) {
-        match &mut self.body {
+    pub fn push(&mut self, id: NodeId, child: NodeId) {
+        let first = self.key(child).first().copied();
+
+        match &mut self.nodes[id as usize].body {
             NodeBody::Children(children) => {
-                children.push(value);
-                children.sort_unstable();
+                children.insert(first, child);
             }
             NodeBody::Value(_value) => {
-                self.convert_value_to_children(Default::default());
-                self.push(value);
+                self.convert_value_to_children(id, 0);
+                self.push(id, child);
             }
         };
     }
 
-    pub fn get_mut(&mut self, index: usize) -> &mut Node<T> {
-        if let NodeBody::Value(_) = self.body {
-            self.convert_value_to_children(Default::default());
-            return self.get_mut(index);
+    /// Detaches `child` from `id`. Like every other node that is no longer
+    /// reachable, it stays in the arena until [`Arena::rebuild`].
+    pub(crate) fn remove(&mut self, id: NodeId, child: NodeId) {
+        let first = self.key(child).first().copied();
+
+        match &mut self.nodes[id as usize].body {
+            NodeBody::Children(children) => children.remove(first),
+            NodeBody::Value(_) => panic!("remove misused!"),
         }
+    }
+
+    /// Folds the only child of `id` into it, undoing a split.
+    pub(crate) fn compact(&mut self, id: NodeId) {
+        let child = match self.children(id) {
+            Some(children) if children.len() == 1 => children.iter().next().unwrap(),
+            _ => return,
+        };
+
+        let (start, len) = {
+            let node = &self.nodes[id as usize];
+            (node.key_start, node.key_len)
+        };
+        let child_node = &self.nodes[child as usize];
 
-        match &mut self.body {
-            NodeBody::Children(children) => &mut children[index],
-            _ => unreachable!(),
+        if start + len == child_node.key_start {
+            // Split keys are still next to each other, so only the length changes.
+            self.nodes[id as usize].key_len += child_node.key_len;
+        } else {
+            let key = [self.key(id), self.key(child)].concat();
+            let start = self.alloc_key(&key);
+            let node = &mut self.nodes[id as usize];
+            node.key_start = start;
+            node.key_len = key.len() as u32;
         }
+
+        let body = self.take_body(child);
+        self.nodes[id as usize].body = body;
     }
 
-    pub(crate) fn set_value(&mut self, value: T) {
-        match &mut self.body {
+    /// Copies every node reachable from the root into a new arena, leaving
+    /// behind detached nodes and key bytes no node refers to any more. Counts
+    /// are left for the caller to recompute.
+    pub(crate) fn rebuild(&self) -> Arena<T> {
+        let mut arena = Arena::new();
+        let mut stack = self
+            .children(ROOT)
+            .into_iter()
+            .flat_map(|children| children.iter())
+            .map(|child| (child, ROOT))
+            .collect::<Vec<_>>();
+
+        while let Some((id, parent)) = stack.pop() {
+            let body = match self.body(id) {
+                NodeBody::Value(value) => NodeBody::Value(*value),
+                NodeBody::Children(_) => NodeBody::Children(Children::default()),
+            };
+            let node = arena.alloc(self.key(id), body);
+            arena.push(parent, node);
+
+            if let Some(children) = self.children(id) {
+                stack.extend(children.iter().map(|child| (child, node)));
+            }
+        }
+
+        arena.nodes.shrink_to_fit();
+        arena.keys.shrink_to_fit();
+        arena
+    }
+
+    pub(crate) fn set_value(&mut self, id: NodeId, value: T) {
+        match &mut self.nodes[id as usize].body {
             NodeBody::Children(_children) => panic!("set_value misused!"),
             NodeBody::Value(old_value) => {
                 *old_value = value;
@@ -175,33 +736,174 @@ impl<T: Integer> Node<T> {
     }
 }
 
+/// A node along with the arena it lives in.
 #[cfg(feature = "alloc")]
-impl<T: Integer> Eq for Node<T> {}
+pub struct NodeRef<'a, T: Integer> {
+    arena: &'a Arena<T>,
+    id: NodeId,
+}
 
 #[cfg(feature = "alloc")]
-impl<T: Integer> PartialEq for Node<T> {
-    fn eq(&self, other: &Self) -> bool {
-        self.key == other.key
+impl<T: Integer> Clone for NodeRef<'_, T> {
+    fn clone(&self) -> Self {
+        *self
     }
 }
 
-pub(crate) fn cmp(a: &[u8], b: &[u8]) -> Ordering {
-    match b.len().cmp(&a.len()) {
-        Ordering::Equal => a.cmp(b),
-        x => x,
+#[cfg(feature = "alloc")]
+impl<T: Integer> Copy for NodeRef<'_, T> {}
+
+#[cfg(feature = "alloc")]
+impl<'a, T: Integer> NodeRef<'a, T> {
+    #[inline]
+    pub(crate) fn id(&self) -> NodeId {
+        self.id
+    }
+
+    #[inline]
+    pub fn key(&self) -> &'a [u8] {
+        self.arena.key(self.id)
+    }
+
+    #[inline]
+    pub fn body(&self) -> &'a NodeBody<T> {
+        self.arena.body(self.id)
+    }
+
+    #[inline]
+    pub fn children(&self) -> impl Iterator<Item = NodeRef<'a, T>> + 'a {
+        let arena = self.arena;
+        arena
+            .children(self.id)
+            .into_iter()
+            .flat_map(|children| children.iter())
+            .map(move |id| arena.node_ref(id))
     }
 }
 
 #[cfg(feature = "alloc")]
-impl<T: Integer> PartialOrd for Node<T> {
-    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
-        Some(Ord::cmp(self, &other))
+impl<T: Integer> Debug for NodeRef<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        struct Children<'a, T: Integer>(NodeRef<'a, T>);
+
+        impl<T: Integer> Debug for Children<'_, T> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.debug_list().entries(self.0.children()).finish()
+            }
+        }
+
+        let mut x = f.debug_struct("Node");
+
+        match core::str::from_utf8(self.key()) {
+            Ok(v) => x.field("key", &v),
+            Err(_) => x.field("key", &self.key()),
+        };
+
+        match self.body() {
+            NodeBody::Children(_) => x.field("children", &Children(*self)),
+            NodeBody::Value(value) => x.field("value", value),
+        };
+        x.finish()
     }
 }
 
 #[cfg(feature = "alloc")]
-impl<T: Integer> Ord for Node<T> {
-    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
-        cmp(&*self.key, &*other.key)
+impl<T: Integer> Display for NodeRef<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match core::str::from_utf8(self.key()) {
+            Ok(v) => f.write_fmt(format_args!("{:?}", v)),
+            Err(_) => f.write_fmt(format_args!("{:?}", self.key())),
+        }?;
+
+        f.write_str(" => ")?;
+
+        match self.body() {
+            NodeBody::Children(_) => {
+                f.write_str("[")?;
+                for child in self.children() {
+                    writeln!(f)?;
+                    let mut indent =
+                        indented(f).with_format(indenter::Format::Uniform { indentation: "  " });
+                    write!(indent, "{}", child)?
+                }
+                f.write_str("\n],")?;
+            }
+            NodeBody::Value(value) => {
+                f.write_fmt(format_args!("{},", value))?;
+            }
+        };
+
+        Ok(())
+    }
+}
+
+pub(crate) fn cmp(a: &[u8], b: &[u8]) -> Ordering {
+    a.cmp(b)
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn node_size() {
+        // Every node pays for the largest inline layout, values included.
+        assert_eq!(core::mem::size_of::<Layout>(), 24);
+        assert_eq!(core::mem::size_of::<Node<u32>>(), 48);
+        assert_eq!(core::mem::size_of::<Node<u64>>(), 48);
+    }
+
+    #[test]
+    fn layouts_grow_in_order() {
+        let mut children = Children::default();
+        let bytes = (0..=255u8).map(|x| x.wrapping_mul(167)).collect::<Vec<_>>();
+
+        for (n, byte) in bytes.iter().enumerate() {
+            children.insert(Some(*byte), n as NodeId + 1);
+
+            let expected = match n + 1 {
+                0..=4 => "Node4",
+                5..=16 => "Node16",
+                17..=48 => "Node48",
+                _ => "Node256",
+            };
+            let layout = match children.layout {
+                Layout::Node4 { .. } => "Node4",
+                Layout::Node16(_) => "Node16",
+                Layout::Node48(_) => "Node48",
+                Layout::Node256 { .. } => "Node256",
+            };
+            assert_eq!(layout, expected);
+
+            let mut seen = bytes[..=n].to_vec();
+            seen.sort_unstable();
+            let ordered = children.layout.iter().map(|x| x.0).collect::<Vec<_>>();
+            assert_eq!(ordered, seen);
+        }
+
+        children.insert(None, 1000);
+        assert_eq!(children.iter().count(), 257);
+        assert_eq!(children.iter().next(), Some(1000));
+        for (n, byte) in bytes.iter().enumerate() {
+            assert_eq!(children.find(*byte), Some(n as NodeId + 1));
+        }
+
+        children.remove(None);
+        for (n, byte) in bytes.iter().enumerate().skip(5) {
+            children.remove(Some(*byte));
+            assert_eq!(children.find(*byte), None);
+
+            for (n, byte) in bytes.iter().enumerate().take(5) {
+                assert_eq!(children.find(*byte), Some(n as NodeId + 1));
+            }
+            assert_eq!(children.len(), 255 - n + 5);
+        }
+
+        assert!(matches!(children.layout, Layout::Node16(_)));
+        children.remove(Some(bytes[0]));
+        children.remove(Some(bytes[1]));
+        assert!(matches!(children.layout, Layout::Node4 { .. }));
+        assert_eq!(children.iter().collect::<Vec<_>>().len(), 3);
     }
 }
@@ -0,0 +1,362 @@
+extern crate alloc;
+
+use alloc::{boxed::Box, vec, vec::Vec};
+use rayon::{
+    iter::plumbing::{bridge_unindexed, Folder, UnindexedConsumer, UnindexedProducer},
+    prelude::*,
+};
+
+use crate::{
+    node::{Arena, NodeBody, NodeId, ROOT},
+    Aggregate, Integer, PathTrie,
+};
+
+/// Groups larger than this are split again by a later byte.
+const SPLIT: usize = 4096;
+
+type Buckets<K, T> = Vec<Vec<(K, T)>>;
+
+/// Groups entries by the first byte at which their keys stop agreeing, past
+/// `start`. Returns that position, the value of a key ending there, and one
+/// group for each byte found there.
+fn partition<K: AsRef<[u8]>, T>(
+    entries: Vec<(K, T)>,
+    start: usize,
+) -> (usize, Option<T>, Buckets<K, T>) {
+    let mut depth = start;
+    if let Some((first, _)) = entries.first() {
+        let first = first.as_ref();
+        while depth < first.len()
+            && entries
+                .iter()
+                .all(|(key, _)| key.as_ref().get(depth) == Some(&first[depth]))
+        {
+            depth += 1;
+        }
+    }
+
+    let mut end = None;
+    let mut buckets = (0..256).map(|_| Vec::new()).collect::<Vec<_>>();
+    for (key, value) in entries {
+        match key.as_ref().get(depth) {
+            Some(byte) => buckets[*byte as usize].push((key, value)),
+            None => end = Some(value),
+        }
+    }
+
+    (depth, end, buckets)
+}
+
+/// Builds the keys of `entries` from `start` onwards into an arena, splitting
+/// large groups across the thread pool.
+fn build<K, T>(entries: Vec<(K, T)>, start: usize) -> Arena<T>
+where
+    K: AsRef<[u8]> + Send,
+    T: Integer + Send,
+{
+    if entries.len() <= SPLIT {
+        let mut trie = PathTrie::<T>::new();
+        for (key, value) in entries {
+            trie.insert(&key.as_ref()[start..], value);
+        }
+        return trie.arena;
+    }
+
+    let prefix = entries[0].0.as_ref().to_vec();
+    let (depth, end, buckets) = partition(entries, start);
+    let prefix = &prefix[start..depth];
+
+    let others = buckets
+        .into_par_iter()
+        .filter(|bucket| !bucket.is_empty())
+        .map(|bucket| build(bucket, depth))
+        .collect::<Vec<_>>();
+
+    let mut arena = Arena::new();
+    let parent = match end {
+        // Every key was the same.
+        Some(value) if others.is_empty() => {
+            let node = arena.alloc(prefix, NodeBody::Value(value));
+            arena.push(ROOT, node);
+            return arena;
+        }
+        _ if prefix.is_empty() => ROOT,
+        _ => {
+            let node = arena.alloc(prefix, NodeBody::Children(Default::default()));
+            arena.push(ROOT, node);
+            node
+        }
+    };
+
+    if let Some(value) = end {
+        let node = arena.alloc(&[], NodeBody::Value(value));
+        arena.push(parent, node);
+    }
+
+    // Every group starts with a different byte, so they can be attached as
+    // they are.
+    for other in others {
+        arena.graft(parent, other);
+    }
+    arena
+}
+
+impl<T: Integer + Send, A: Aggregate<T>> PathTrie<T, A> {
+    /// Builds a trie from a parallel iterator.
+    ///
+    /// Keys are grouped by the first byte past the prefix they all share, and
+    /// groups that are still large are split again further in, so keys that
+    /// all start with the same components are spread out as well. Each group
+    /// is built into its own trie on the thread pool, and the results are
+    /// moved under a single root. As with [`PathTrie::insert`], the last value
+    /// given for a key wins.
+    pub fn par_from_iter<I, K>(iter: I) -> Self
+    where
+        I: IntoParallelIterator<Item = (K, T)>,
+        K: AsRef<[u8]> + Send,
+    {
+        let entries = iter.into_par_iter().collect::<Vec<_>>();
+
+        let mut trie = Self::default();
+        trie.arena.graft(ROOT, build(entries, 0));
+        trie.update_all();
+        trie
+    }
+
+    /// Inserts every entry of a parallel iterator, replacing existing values.
+    pub fn par_extend<I, K>(&mut self, iter: I)
+    where
+        I: IntoParallelIterator<Item = (K, T)>,
+        K: AsRef<[u8]> + Send,
+    {
+        let other = Self::par_from_iter(iter);
+        self.merge(other, |_, _, theirs| theirs);
+    }
+}
+
+impl<T: Integer + Sync, A: Aggregate<T>> PathTrie<T, A> {
+    /// Iterates over the trie's entries in parallel, handing out disjoint
+    /// subtrees to each thread.
+    #[inline]
+    pub fn par_iter(&self) -> ParEntries<'_, T> {
+        ParEntries(&self.arena)
+    }
+
+    #[inline]
+    pub fn par_values(&self) -> ParValues<'_, T> {
+        ParValues(&self.arena)
+    }
+}
+
+impl<'a, T: Integer + Sync, A: Aggregate<T>> IntoParallelIterator for &'a PathTrie<T, A> {
+    type Iter = ParEntries<'a, T>;
+    type Item = (Box<[u8]>, &'a T);
+
+    #[inline]
+    fn into_par_iter(self) -> Self::Iter {
+        ParEntries(&self.arena)
+    }
+}
+
+pub struct ParEntries<'a, T: Integer>(&'a Arena<T>);
+
+impl<'a, T: Integer + Sync> ParallelIterator for ParEntries<'a, T> {
+    type Item = (Box<[u8]>, &'a T);
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+    where
+        C: UnindexedConsumer<Self::Item>,
+    {
+        let producer = Subtries::new(self.0, |key: &[u8], value| (key.into(), value));
+        bridge_unindexed(producer, consumer)
+    }
+}
+
+pub struct ParValues<'a, T: Integer>(&'a Arena<T>);
+
+impl<'a, T: Integer + Sync> ParallelIterator for ParValues<'a, T> {
+    type Item = &'a T;
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+    where
+        C: UnindexedConsumer<Self::Item>,
+    {
+        let producer = Subtries::new(self.0, |_: &[u8], value| value);
+        bridge_unindexed(producer, consumer)
+    }
+}
+
+/// A run of sibling subtrees, all below the same key.
+struct Subtries<'a, T: Integer, F> {
+    arena: &'a Arena<T>,
+    prefix: Vec<u8>,
+    nodes: Vec<NodeId>,
+    map: F,
+}
+
+impl<'a, T: Integer, F> Subtries<'a, T, F> {
+    #[inline]
+    fn new(arena: &'a Arena<T>, map: F) -> Self {
+        Subtries {
+            arena,
+            prefix: vec![],
+            nodes: vec![ROOT],
+            map,
+        }
+    }
+}
+
+impl<'a, T, F, R> UnindexedProducer for Subtries<'a, T, F>
+where
+    T: Integer + Sync,
+    F: Fn(&[u8], &'a T) -> R + Copy + Send,
+{
+    type Item = R;
+
+    fn split(mut self) -> (Self, Option<Self>) {
+        // A lone subtree is split between its children instead.
+        while let [id] = *self.nodes {
+            match self.arena.children(id) {
+                Some(children) => {
+                    self.prefix.extend_from_slice(self.arena.key(id));
+                    self.nodes = children.iter().collect();
+                }
+                None => return (self, None),
+            }
+        }
+
+        if self.nodes.len() < 2 {
+            return (self, None);
+        }
+
+        let right = Subtries {
+            arena: self.arena,
+            prefix: self.prefix.clone(),
+            nodes: self.nodes.split_off(self.nodes.len() / 2),
+            map: self.map,
+        };
+        (self, Some(right))
+    }
+
+    fn fold_with<Fo>(self, mut folder: Fo) -> Fo
+    where
+        Fo: Folder<Self::Item>,
+    {
+        let mut key = self.prefix;
+        let depth = key.len();
+        let mut stack = self
+            .nodes
+            .iter()
+            .rev()
+            .map(|id| (*id, depth))
+            .collect::<Vec<_>>();
+
+        while let Some((id, depth)) = stack.pop() {
+            if folder.full() {
+                break;
+            }
+
+            key.truncate(depth);
+            key.extend_from_slice(self.arena.key(id));
+
+            match self.arena.body(id) {
+                NodeBody::Children(children) => {
+                    let depth = key.len();
+                    let start = stack.len();
+                    stack.extend(children.iter().map(|x| (x, depth)));
+                    stack[start..].reverse();
+                }
+                NodeBody::Value(value) => folder = folder.consume((self.map)(&key, value)),
+            }
+        }
+
+        folder
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    fn paths() -> Vec<(String, u32)> {
+        (0..10_000u32)
+            .map(|n| (format!("{}/{}/{}", n % 37, n % 101, n), n))
+            .chain(vec![(String::new(), 1), ("1".to_string(), 2)])
+            .collect()
+    }
+
+    #[test]
+    fn par_from_iter() {
+        let paths = paths();
+
+        let trie = PathTrie::<u32>::par_from_iter(paths.clone());
+        let mut expected = PathTrie::new();
+        for (path, n) in paths.iter() {
+            expected.insert(path, *n);
+        }
+
+        assert_eq!(format!("{:?}", trie), format!("{:?}", expected));
+        assert_eq!(trie.get("35/0/5252"), Some(5252));
+        assert_eq!(trie.get(""), Some(1));
+    }
+
+    #[test]
+    fn par_from_iter_shared_prefix() {
+        let paths = (0..20_000u32)
+            .map(|n| (format!("/home/user/{}/{}", n % 53, n), n))
+            .chain(vec![
+                ("/home/user".to_string(), 1),
+                ("/home/user".to_string(), 2),
+            ])
+            .collect::<Vec<_>>();
+
+        let (depth, end, buckets) = partition(paths.clone(), 0);
+        assert_eq!(depth, "/home/user".len());
+        assert_eq!(end, Some(2));
+        assert_eq!(buckets.iter().filter(|x| !x.is_empty()).count(), 1);
+
+        // The only group left is split again past "/home/user/", by the first
+        // digit of each directory.
+        let (depth, _, buckets) = partition(buckets[b'/' as usize].clone(), depth);
+        assert_eq!(depth, "/home/user/".len());
+        assert_eq!(buckets.iter().filter(|x| !x.is_empty()).count(), 10);
+
+        let trie = PathTrie::<u32>::par_from_iter(paths.clone());
+        let mut expected = PathTrie::new();
+        for (path, n) in paths.iter() {
+            expected.insert(path, *n);
+        }
+
+        assert_eq!(format!("{:?}", trie), format!("{:?}", expected));
+        assert_eq!(trie.len(), 20_001);
+        assert_eq!(trie.get("/home/user"), Some(2));
+        assert_eq!(trie.get("/home/user/7/7"), Some(7));
+    }
+
+    #[test]
+    fn par_extend() {
+        let mut trie = PathTrie::<u32>::new();
+        trie.insert("1/1/1", 0);
+        trie.insert("a", 0);
+
+        trie.par_extend(paths().into_par_iter().map(|(path, n)| (path, n + 1)));
+
+        assert_eq!(trie.keys().count(), 10_003);
+        assert_eq!(trie.get("1/1/1"), Some(2));
+        assert_eq!(trie.get("a"), Some(0));
+    }
+
+    #[test]
+    fn par_iter() {
+        let trie = PathTrie::<u32>::par_from_iter(paths());
+
+        let entries = trie.par_iter().collect::<Vec<_>>();
+        assert_eq!(entries, trie.entries().collect::<Vec<_>>());
+
+        let sum = trie.par_values().map(|x| *x as u64).sum::<u64>();
+        assert_eq!(sum, trie.values().map(|x| *x as u64).sum::<u64>());
+
+        let empty = PathTrie::<u32>::new();
+        assert_eq!((&empty).into_par_iter().count(), 0);
+    }
+}
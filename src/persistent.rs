@@ -0,0 +1,374 @@
+extern crate alloc;
+
+use crate::{diff::Source, lcp::find_common_prefix, lcp::Prefix, Integer};
+use alloc::{boxed::Box, sync::Arc, vec, vec::Vec};
+
+#[derive(Debug, Clone)]
+pub enum NodeBody<T: Integer> {
+    Children(Vec<Arc<Node<T>>>),
+    Value(T),
+}
+
+#[derive(Debug, Clone)]
+pub struct Node<T: Integer> {
+    key: Box<[u8]>,
+    body: NodeBody<T>,
+}
+
+impl<T: Integer> Node<T> {
+    #[inline]
+    fn leaf(key: &[u8], value: T) -> Arc<Node<T>> {
+        Arc::new(Node {
+            key: key.to_vec().into_boxed_slice(),
+            body: NodeBody::Value(value),
+        })
+    }
+
+    fn children_mut(&mut self) -> &mut Vec<Arc<Node<T>>> {
+        if let NodeBody::Value(value) = self.body {
+            self.body = NodeBody::Children(vec![Node::leaf(&[], value)]);
+        }
+
+        match &mut self.body {
+            NodeBody::Children(children) => children,
+            NodeBody::Value(_) => unreachable!(),
+        }
+    }
+
+    fn push(&mut self, child: Arc<Node<T>>) {
+        let children = self.children_mut();
+        let index = children
+            .binary_search_by(|x| x.key.cmp(&child.key))
+            .unwrap_or_else(|i| i);
+        children.insert(index, child);
+    }
+
+    fn split(&mut self, at: usize) {
+        let rest = Node {
+            key: self.key[at..].to_vec().into_boxed_slice(),
+            body: core::mem::replace(&mut self.body, NodeBody::Children(vec![])),
+        };
+        self.key = self.key[..at].to_vec().into_boxed_slice();
+        self.push(Arc::new(rest));
+    }
+
+    #[inline]
+    fn find(&self, key: &[u8]) -> Option<(usize, Prefix)> {
+        let children = match &self.body {
+            NodeBody::Children(children) => children,
+            NodeBody::Value(_) => return None,
+        };
+
+        if key.is_empty() {
+            return children
+                .first()
+                .filter(|x| x.key.is_empty())
+                .map(|_| (0, Prefix::Exact));
+        }
+
+        children
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| !x.key.is_empty())
+            .map(|(i, x)| (i, find_common_prefix(&x.key, key)))
+            .find(|(_, prefix)| !matches!(prefix, Prefix::NoMatch(_)))
+    }
+
+    /// Collapses a node left with a single child back into its parent edge.
+    fn compact(&mut self) {
+        let child = match &self.body {
+            NodeBody::Children(children) if children.len() == 1 => children[0].clone(),
+            _ => return,
+        };
+
+        self.key = [&*self.key, &*child.key].concat().into_boxed_slice();
+        self.body = child.body.clone();
+    }
+}
+
+/// A [`PathTrie`](crate::PathTrie) whose nodes are shared between clones.
+///
+/// Cloning is O(1), and each insert or remove only copies the nodes on the
+/// path from the root to the changed node, so older clones remain valid
+/// snapshots.
+#[derive(Debug, Clone)]
+pub struct PersistentPathTrie<T: Integer> {
+    root: Arc<Node<T>>,
+}
+
+impl<T: Integer> Default for PersistentPathTrie<T> {
+    fn default() -> Self {
+        PersistentPathTrie::new()
+    }
+}
+
+impl<T: Integer> PersistentPathTrie<T> {
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            root: Arc::new(Node {
+                key: Default::default(),
+                body: NodeBody::Children(vec![]),
+            }),
+        }
+    }
+
+    #[inline]
+    pub fn keys<'a>(&'a self) -> impl Iterator<Item = Box<[u8]>> + 'a {
+        self.entries().map(|x| x.0)
+    }
+
+    #[inline]
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.entries().map(|x| x.1)
+    }
+
+    #[inline]
+    pub fn entries(&self) -> Entries<'_, T> {
+        Entries {
+            key: vec![],
+            stack: vec![(&*self.root, 0)],
+        }
+    }
+
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Option<T> {
+        let mut key = key.as_ref();
+        let mut node = &*self.root;
+
+        loop {
+            let (i, prefix) = node.find(key)?;
+            let child = match &node.body {
+                NodeBody::Children(children) => &*children[i],
+                NodeBody::Value(_) => unreachable!(),
+            };
+
+            match (prefix, &child.body) {
+                (Prefix::Exact, NodeBody::Value(value)) => return Some(*value),
+                (Prefix::Exact, NodeBody::Children(_)) => key = &[],
+                (Prefix::Incomplete(partial), NodeBody::Children(_)) => key = &key[partial..],
+                _ => return None,
+            }
+
+            node = child;
+        }
+    }
+
+    #[inline]
+    pub fn insert<K: AsRef<[u8]>>(&mut self, key: K, value: T) {
+        Self::insert_inner(Arc::make_mut(&mut self.root), key.as_ref(), value)
+    }
+
+    fn insert_inner(node: &mut Node<T>, key: &[u8], value: T) {
+        let (i, prefix) = match node.find(key) {
+            Some(found) => found,
+            None => return node.push(Node::leaf(key, value)),
+        };
+        let child = Arc::make_mut(&mut node.children_mut()[i]);
+
+        match prefix {
+            Prefix::Exact => match &mut child.body {
+                NodeBody::Value(old_value) => *old_value = value,
+                NodeBody::Children(_) => Self::insert_inner(child, &[], value),
+            },
+            Prefix::Incomplete(partial) => {
+                Self::insert_inner(child, &key[partial..], value);
+            }
+            Prefix::PerfectSubset(partial) => {
+                child.split(partial);
+                child.push(Node::leaf(&[], value));
+            }
+            Prefix::Divergent(partial) => {
+                child.split(partial);
+                child.push(Node::leaf(&key[partial..], value));
+            }
+            Prefix::NoMatch(_) => unreachable!(),
+        }
+    }
+
+    pub fn remove<K: AsRef<[u8]>>(&mut self, key: K) -> Option<T> {
+        let key = key.as_ref();
+
+        // Avoid copying the path to a key that isn't there.
+        self.get(key)?;
+        Self::remove_inner(Arc::make_mut(&mut self.root), key)
+    }
+
+    fn remove_inner(node: &mut Node<T>, key: &[u8]) -> Option<T> {
+        let (i, prefix) = node.find(key)?;
+        let children = node.children_mut();
+
+        let value = match (prefix, &children[i].body) {
+            (Prefix::Exact, NodeBody::Value(value)) => {
+                let value = *value;
+                children.remove(i);
+                return Some(value);
+            }
+            (Prefix::Exact, NodeBody::Children(_)) => {
+                Self::remove_inner(Arc::make_mut(&mut children[i]), &[])?
+            }
+            (Prefix::Incomplete(partial), NodeBody::Children(_)) => {
+                Self::remove_inner(Arc::make_mut(&mut children[i]), &key[partial..])?
+            }
+            _ => return None,
+        };
+
+        let child = Arc::make_mut(&mut children[i]);
+        match &child.body {
+            NodeBody::Children(grandchildren) if grandchildren.is_empty() => {
+                children.remove(i);
+            }
+            _ => child.compact(),
+        }
+
+        Some(value)
+    }
+}
+
+pub struct Entries<'a, T: Integer> {
+    key: Vec<u8>,
+    stack: Vec<(&'a Node<T>, usize)>,
+}
+
+impl<'a, T: Integer> Iterator for Entries<'a, T> {
+    type Item = (Box<[u8]>, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (node, depth) = self.stack.pop()?;
+            self.key.truncate(depth);
+            self.key.extend_from_slice(&node.key);
+
+            match &node.body {
+                NodeBody::Children(children) => {
+                    let depth = self.key.len();
+                    self.stack
+                        .extend(children.iter().rev().map(|child| (&**child, depth)));
+                }
+                NodeBody::Value(value) => {
+                    return Some((self.key.to_vec().into_boxed_slice(), value));
+                }
+            }
+        }
+    }
+}
+
+impl<'a, T: Integer + 'a> Source<'a, T> for PersistentPathTrie<T> {
+    type Node = &'a Node<T>;
+
+    fn roots(&'a self, out: &mut Vec<Self::Node>) {
+        self.children(&self.root, out)
+    }
+
+    fn children(&'a self, node: Self::Node, out: &mut Vec<Self::Node>) {
+        if let NodeBody::Children(children) = &node.body {
+            out.extend(children.iter().map(|x| &**x));
+        }
+    }
+
+    fn key(&self, node: Self::Node) -> &'a [u8] {
+        &node.key
+    }
+
+    fn value(&self, node: Self::Node) -> Option<T> {
+        match node.body {
+            NodeBody::Value(value) => Some(value),
+            NodeBody::Children(_) => None,
+        }
+    }
+
+    fn id(&self, node: Self::Node) -> *const u8 {
+        node as *const _ as *const u8
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::{diff, Change};
+
+    const PATHS: &[&str] = &[
+        "bacon/sandwich/hamburger",
+        "bacon/sandwich/ham",
+        "bacon",
+        "bacon/sandwich/ham-replacement",
+        "bacon/baguette/croissant",
+        "bacon/jam",
+        "bacon/wat",
+        "break-everything/haha",
+    ];
+
+    #[test]
+    fn snapshots() {
+        let mut trie = PersistentPathTrie::<u32>::new();
+
+        for (n, path) in PATHS.iter().enumerate() {
+            trie.insert(path, n as u32 + 1);
+        }
+
+        let snapshot = trie.clone();
+        trie.insert("bacon/sandwich/ham", 100);
+        trie.insert("apple", 200);
+        assert_eq!(trie.remove("bacon/jam"), Some(6));
+        assert_eq!(trie.remove("bacon/jam"), None);
+        assert_eq!(trie.remove("bacon/sand"), None);
+
+        for (n, path) in PATHS.iter().enumerate() {
+            assert_eq!(snapshot.get(path), Some(n as u32 + 1));
+        }
+        assert_eq!(snapshot.keys().count(), PATHS.len());
+
+        assert_eq!(trie.get("bacon/sandwich/ham"), Some(100));
+        assert_eq!(trie.get("apple"), Some(200));
+        assert_eq!(trie.get("bacon/jam"), None);
+        assert_eq!(trie.get("bacon"), Some(3));
+        assert_eq!(trie.keys().count(), PATHS.len());
+
+        let changes = diff(&snapshot, &trie).collect::<Vec<_>>();
+        assert_eq!(
+            changes,
+            vec![
+                Change::Added(b"apple".to_vec().into_boxed_slice(), 200),
+                Change::Removed(b"bacon/jam".to_vec().into_boxed_slice(), 6),
+                Change::Changed(b"bacon/sandwich/ham".to_vec().into_boxed_slice(), 2, 100),
+            ]
+        );
+    }
+
+    #[test]
+    fn structural_sharing() {
+        let mut trie = PersistentPathTrie::<u32>::new();
+        trie.insert("a/1", 1);
+        trie.insert("a/2", 2);
+        trie.insert("b/1", 3);
+
+        let snapshot = trie.clone();
+        trie.insert("a/3", 4);
+
+        let (old, new) = match (&snapshot.root.body, &trie.root.body) {
+            (NodeBody::Children(old), NodeBody::Children(new)) => (old, new),
+            _ => unreachable!(),
+        };
+        assert!(!Arc::ptr_eq(&old[0], &new[0]));
+        assert!(Arc::ptr_eq(&old[1], &new[1]));
+    }
+
+    #[test]
+    fn remove_compacts() {
+        let mut trie = PersistentPathTrie::<u32>::new();
+        trie.insert("abc", 1);
+        trie.insert("abd", 2);
+        trie.insert("ab", 3);
+
+        assert_eq!(trie.remove("abd"), Some(2));
+        assert_eq!(trie.remove("ab"), Some(3));
+
+        match &trie.root.body {
+            NodeBody::Children(children) => {
+                assert_eq!(children.len(), 1);
+                assert_eq!(&*children[0].key, b"abc");
+            }
+            NodeBody::Value(_) => unreachable!(),
+        }
+        assert_eq!(trie.get("abc"), Some(1));
+    }
+}
@@ -0,0 +1,248 @@
+extern crate alloc;
+
+use alloc::{vec, vec::Vec};
+use core::{convert::TryFrom, fmt::Debug, mem::size_of};
+
+use crate::{fst, node::NodeBody, Aggregate, Fst, Integer, Offset, PathTrie};
+
+/// The shape of a [`PathTrie`] or [`Fst`], as returned by their `stats` methods.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stats {
+    /// Nodes below the root, including the empty-keyed ones holding the
+    /// value of a key that is a prefix of others.
+    pub nodes: usize,
+    pub values: usize,
+    /// The most nodes between the root and a value.
+    pub max_depth: usize,
+    pub average_depth: f64,
+    /// The number of nodes with each number of children, indexed by that
+    /// number. Nodes holding a value aren't counted.
+    pub fanout: Vec<usize>,
+    /// Key bytes held by the nodes themselves.
+    pub key_bytes: usize,
+    /// Key bytes of every full key put together.
+    pub logical_key_bytes: usize,
+    pub fst_size: FstSizes,
+}
+
+impl Stats {
+    /// How many logical key bytes each stored one stands for, which grows
+    /// with the prefixes the keys share.
+    #[inline]
+    pub fn sharing_ratio(&self) -> f64 {
+        if self.key_bytes == 0 {
+            return 1.0;
+        }
+
+        self.logical_key_bytes as f64 / self.key_bytes as f64
+    }
+
+    fn node(&mut self, key_len: usize, children: usize) {
+        self.nodes += 1;
+        self.key_bytes += key_len;
+        self.group(children);
+    }
+
+    fn group(&mut self, children: usize) {
+        if self.fanout.len() <= children {
+            self.fanout.resize(children + 1, 0);
+        }
+        self.fanout[children] += 1;
+    }
+
+    fn value(&mut self, key_len: usize, logical_len: usize, depth: usize) {
+        self.nodes += 1;
+        self.values += 1;
+        self.key_bytes += key_len;
+        self.logical_key_bytes += logical_len;
+        self.max_depth = self.max_depth.max(depth);
+        // Summed here, and divided once everything has been visited.
+        self.average_depth += depth as f64;
+    }
+
+    fn finish(mut self) -> Self {
+        if self.values > 0 {
+            self.average_depth /= self.values as f64;
+        }
+        self
+    }
+}
+
+/// The size in bytes of an FST for each value width, or `None` where one
+/// couldn't be written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FstSizes {
+    pub u8: Option<usize>,
+    pub u16: Option<usize>,
+    pub u32: Option<usize>,
+    pub u64: Option<usize>,
+    pub u128: Option<usize>,
+}
+
+impl FstSizes {
+    fn set(&mut self, width: usize, size: usize) {
+        match width {
+            1 => self.u8 = Some(size),
+            2 => self.u16 = Some(size),
+            4 => self.u32 = Some(size),
+            8 => self.u64 = Some(size),
+            _ => self.u128 = Some(size),
+        }
+    }
+}
+
+impl<T: Integer, A: Aggregate<T>> PathTrie<T, A> {
+    /// Walks the whole trie to describe its shape, along with what
+    /// [`write_fst`](Self::write_fst) would make of it at each value width.
+    pub fn stats(&self) -> Stats {
+        let mut stats = Stats::default();
+        let mut stack = vec![(self.root(), 0, 0)];
+
+        while let Some((node, depth, parent_len)) = stack.pop() {
+            let key_len = node.key().len();
+
+            match node.body() {
+                NodeBody::Value(_) => stats.value(key_len, parent_len + key_len, depth),
+                NodeBody::Children(_) => {
+                    let len = stack.len();
+                    stack.extend(
+                        node.children()
+                            .map(|child| (child, depth + 1, parent_len + key_len)),
+                    );
+
+                    let children = stack.len() - len;
+                    if depth == 0 {
+                        stats.group(children);
+                    } else {
+                        stats.node(key_len, children);
+                    }
+                }
+            }
+        }
+
+        stats.fst_size = FstSizes {
+            u8: self.fst_len::<u8>(),
+            u16: self.fst_len::<u16>(),
+            u32: self.fst_len::<u32>(),
+            u64: self.fst_len::<u64>(),
+            u128: self.fst_len::<u128>(),
+        };
+        stats.finish()
+    }
+
+    #[inline]
+    fn fst_len<U: Integer>(&self) -> Option<usize> {
+        let mut last = 0;
+        let len = self.fst_layout::<U, u32, _>(|_, offset| last = offset);
+        u32::try_from(last).ok()?;
+        usize::try_from(len).ok()
+    }
+}
+
+impl<'data, T: Integer + Debug, O: Offset> Fst<'data, T, O> {
+    /// Walks the whole FST to describe its shape. Only the size for `T` is
+    /// known, which is where the last group found ends, whatever the width
+    /// of its offsets and whatever follows it in the data.
+    pub fn stats(&self) -> Stats {
+        let mut stats = Stats::default();
+        let roots = self.roots().collect::<Vec<_>>();
+        stats.group(roots.len());
+
+        // Each group is ended by an empty node the size of the alignment.
+        let alignment = fst::alignment::<T, O>();
+        let mut size = match roots.last() {
+            Some(node) => self.end_of(*node),
+            None => fst::start_offset::<T, O>(),
+        } + alignment;
+
+        let mut stack = roots
+            .into_iter()
+            .map(|node| (node, 1, 0))
+            .collect::<Vec<_>>();
+
+        while let Some((node, depth, parent_len)) = stack.pop() {
+            let key_len = node.key().len();
+
+            if node.terminus().is_some() {
+                stats.value(key_len, parent_len + key_len, depth);
+                continue;
+            }
+
+            let len = stack.len();
+            stack.extend(
+                self.children(node)
+                    .map(|child| (child, depth + 1, parent_len + key_len)),
+            );
+            stats.node(key_len, stack.len() - len);
+
+            if let Some((last, _, _)) = stack[len..].last() {
+                size = size.max(self.end_of(*last) + alignment);
+            }
+        }
+
+        stats.fst_size.set(size_of::<T>(), size);
+        stats.finish()
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use memmap::Mmap;
+    use std::fs::File;
+    use std::io::Cursor;
+
+    #[test]
+    fn stats() {
+        let mut trie = PathTrie::<u32>::new();
+        for (n, path) in ["src/lib.rs", "src/node.rs", "src", "README.md"]
+            .iter()
+            .enumerate()
+        {
+            trie.insert(path, n as u32);
+        }
+
+        let stats = trie.stats();
+        assert_eq!(stats.values, 4);
+        // "README.md", "src" -> "", "/", "lib.rs", "node.rs"
+        assert_eq!(stats.nodes, 6);
+        assert_eq!(stats.max_depth, 3);
+        assert_eq!(stats.average_depth, 2.25);
+        assert_eq!(stats.fanout, vec![0, 0, 3]);
+        assert_eq!(stats.key_bytes, 9 + 3 + 1 + 6 + 7);
+        assert_eq!(stats.logical_key_bytes, 10 + 11 + 3 + 9);
+
+        let mut buf = Cursor::new(vec![]);
+        trie.write_fst(&mut buf).unwrap();
+        let buf = buf.into_inner();
+        assert_eq!(stats.fst_size.u32, Some(buf.len()));
+
+        std::fs::write("./test-stats.fst", buf).unwrap();
+        let mmap = unsafe { Mmap::map(&File::open("./test-stats.fst").unwrap()).unwrap() };
+        let fst = Fst::<u32>::new(&mmap).unwrap();
+
+        let fst_stats = fst.stats();
+        assert_eq!(fst_stats.fst_size.u32, stats.fst_size.u32);
+
+        // Only the FST itself counts, not whatever follows it.
+        let mut trailing = mmap.to_vec();
+        trailing.extend_from_slice(&[0xff; 7]);
+        let trailing = Fst::<u32>::new(&trailing).unwrap().stats();
+        assert_eq!(trailing.fst_size.u32, stats.fst_size.u32);
+
+        let empty = PathTrie::<u32>::new();
+        let mut buf = vec![];
+        empty.write_fst(&mut buf).unwrap();
+        buf.push(0);
+        let empty_stats = Fst::<u32>::new(&buf).unwrap().stats();
+        assert_eq!(empty_stats.fst_size.u32, Some(empty.fst_size()));
+        assert_eq!(fst_stats.fst_size.u64, None);
+        assert_eq!(
+            fst_stats,
+            Stats {
+                fst_size: fst_stats.fst_size,
+                ..stats
+            }
+        );
+    }
+}
@@ -0,0 +1,249 @@
+extern crate alloc;
+
+use alloc::{collections::BTreeMap, string::String, vec, vec::Vec};
+use core::fmt::{self, Display, Write};
+
+use crate::{Aggregate, Integer, PathTrie};
+
+/// Draws the keys of a [`PathTrie`] as a directory tree, one path component
+/// per line, in the style of the Unix `tree` command.
+///
+/// Keys starting with `/` are drawn under a `/` component of their own.
+///
+/// Made by [`PathTrie::tree`]; this is also what `PathTrie`'s `Display` prints.
+pub struct Tree<'a, T: Integer, A: Aggregate<T>> {
+    trie: &'a PathTrie<T, A>,
+    max_depth: Option<usize>,
+    show_values: bool,
+    collapse: bool,
+}
+
+impl<'a, T: Integer, A: Aggregate<T>> Tree<'a, T, A> {
+    #[inline]
+    pub(crate) fn new(trie: &'a PathTrie<T, A>) -> Self {
+        Tree {
+            trie,
+            max_depth: None,
+            show_values: true,
+            collapse: false,
+        }
+    }
+
+    /// Stops drawing below `depth` components.
+    #[inline]
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Whether to print the value of each key after it. On by default.
+    #[inline]
+    pub fn show_values(mut self, show: bool) -> Self {
+        self.show_values = show;
+        self
+    }
+
+    /// Whether to draw a chain of components with only one child each on a
+    /// single line, as in `src/bin/main.rs`.
+    #[inline]
+    pub fn collapse(mut self, collapse: bool) -> Self {
+        self.collapse = collapse;
+        self
+    }
+}
+
+struct Component<'a, T> {
+    name: &'a [u8],
+    value: Option<&'a T>,
+    children: Vec<usize>,
+}
+
+impl<T: Integer, A: Aggregate<T>> Display for Tree<'_, T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let keys = self.trie.entries().collect::<Vec<_>>();
+
+        // Components are collected into a tree of their own first, as keys in
+        // the same directory aren't necessarily next to each other, with
+        // `a.txt` sorting between `a` and `a/b`.
+        let mut components = vec![Component {
+            name: &[],
+            value: None,
+            children: vec![],
+        }];
+        // The child of each component with each name.
+        let mut lookup = BTreeMap::new();
+
+        'keys: for (key, value) in keys.iter() {
+            let mut current = 0;
+
+            for (depth, name) in key.split(|x| *x == b'/').enumerate() {
+                if self.max_depth.filter(|max| depth >= *max).is_some() {
+                    continue 'keys;
+                }
+
+                current = *lookup.entry((current, name)).or_insert_with(|| {
+                    components.push(Component {
+                        name,
+                        value: None,
+                        children: vec![],
+                    });
+                    let child = components.len() - 1;
+                    components[current].children.push(child);
+                    child
+                });
+            }
+
+            components[current].value = Some(*value);
+        }
+
+        f.write_str(".")?;
+
+        // Whether the component at each depth so far was the last of its siblings.
+        let mut lasts = Vec::new();
+        let mut stack = components[0]
+            .children
+            .iter()
+            .rev()
+            .enumerate()
+            .map(|(n, x)| (*x, 1, n == 0))
+            .collect::<Vec<_>>();
+
+        while let Some((mut id, depth, last)) = stack.pop() {
+            lasts.truncate(depth - 1);
+
+            f.write_char('\n')?;
+            for last in lasts.iter() {
+                f.write_str(if *last { "    " } else { "│   " })?;
+            }
+            f.write_str(if last { "└── " } else { "├── " })?;
+            lasts.push(last);
+
+            let mut name = match components[id].name {
+                // The empty component before the first `/` of an absolute path.
+                b"" if depth == 1 => "/".into(),
+                name => String::from_utf8_lossy(name),
+            };
+            while self.collapse
+                && components[id].value.is_none()
+                && components[id].children.len() == 1
+            {
+                id = components[id].children[0];
+                let name = name.to_mut();
+                if !name.ends_with('/') {
+                    name.push('/');
+                }
+                name.push_str(&String::from_utf8_lossy(components[id].name));
+            }
+
+            f.write_str(&name)?;
+            if let (true, Some(value)) = (self.show_values, components[id].value) {
+                write!(f, " ({})", value)?;
+            }
+
+            stack.extend(
+                components[id]
+                    .children
+                    .iter()
+                    .rev()
+                    .enumerate()
+                    .map(|(n, x)| (*x, depth + 1, n == 0)),
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    fn trie() -> PathTrie<u32> {
+        let mut trie = PathTrie::new();
+        for (n, path) in [
+            "src/lib.rs",
+            "src/node/mod.rs",
+            "src",
+            "src.txt",
+            "src/bin/tool/main.rs",
+            "README.md",
+        ]
+        .iter()
+        .enumerate()
+        {
+            trie.insert(path, n as u32);
+        }
+        trie
+    }
+
+    #[test]
+    fn tree() {
+        let trie = trie();
+
+        assert_eq!(
+            trie.to_string(),
+            "\
+.
+├── README.md (5)
+├── src (2)
+│   ├── bin
+│   │   └── tool
+│   │       └── main.rs (4)
+│   ├── lib.rs (0)
+│   └── node
+│       └── mod.rs (1)
+└── src.txt (3)"
+        );
+
+        assert_eq!(
+            trie.tree().collapse(true).show_values(false).to_string(),
+            "\
+.
+├── README.md
+├── src
+│   ├── bin/tool/main.rs
+│   ├── lib.rs
+│   └── node/mod.rs
+└── src.txt"
+        );
+
+        assert_eq!(
+            trie.tree().max_depth(2).to_string(),
+            "\
+.
+├── README.md (5)
+├── src (2)
+│   ├── bin
+│   ├── lib.rs (0)
+│   └── node
+└── src.txt (3)"
+        );
+
+        assert_eq!(PathTrie::<u32>::new().to_string(), ".");
+
+        let mut trie = PathTrie::new();
+        trie.insert("/usr/bin/env", 0u32);
+        trie.insert("/etc", 1);
+        trie.insert("usr", 2);
+        assert_eq!(
+            trie.tree().show_values(false).to_string(),
+            "\
+.
+├── /
+│   ├── etc
+│   └── usr
+│       └── bin
+│           └── env
+└── usr"
+        );
+        assert_eq!(
+            trie.tree().collapse(true).max_depth(3).to_string(),
+            "\
+.
+├── /
+│   ├── etc (1)
+│   └── usr/bin
+└── usr (2)"
+        );
+    }
+}
@@ -1,26 +1,24 @@
 extern crate alloc;
 
-use bare_io::{Seek, SeekFrom, Write};
-
-use core::{cmp::Ordering, convert::TryInto, fmt::Debug, mem::size_of};
-
-use crate::{fst, lcp::find_common_prefix, lcp::Prefix, node::Node, node::NodeBody, Integer};
-use alloc::{boxed::Box, collections::BTreeMap, vec, vec::Vec};
-
-#[cfg(feature = "alloc")]
-impl<'a, T: Integer> RawEntries<'a, T> {
-    #[inline(always)]
-    fn new(node: &'a Node<T>, parent: Box<[u8]>, depth: usize) -> Self {
-        Self {
-            node,
-            cur: 0,
-            child_cur: 0,
-            parent,
-            child: None,
-            depth,
-        }
-    }
-}
+use bare_io::Write;
+
+use core::{
+    cmp::Ordering,
+    fmt::{self, Debug, Display},
+    marker::PhantomData,
+    mem::size_of,
+};
+
+use crate::{
+    aggregate::Aggregate,
+    fst,
+    lcp::find_common_prefix,
+    lcp::Prefix,
+    node::{Arena, Children, NodeBody, NodeId, NodeRef, ROOT},
+    tree::Tree,
+    Integer, Streamer,
+};
+use alloc::{boxed::Box, collections::BTreeMap, string::String, vec, vec::Vec};
 
 #[derive(Debug, Clone, Copy)]
 pub enum EntryType {
@@ -30,138 +28,188 @@ pub enum EntryType {
 
 #[derive(Debug, Clone)]
 pub struct RawEntry<'a, T: Integer> {
-    pub node: &'a Node<T>,
+    pub node: NodeRef<'a, T>,
     pub parent: Box<[u8]>,
     pub depth: usize,
     pub ty: EntryType,
 }
 
-pub struct RawEntries<'a, T: Integer> {
-    node: &'a Node<T>,
-    cur: usize,
-    child_cur: usize,
-    parent: Box<[u8]>,
-    child: Option<Box<RawEntries<'a, T>>>,
+/// A [`RawEntry`] whose parent key is lent by a [`RawStream`].
+#[derive(Debug, Clone)]
+pub struct RawEntryRef<'a, 's, T: Integer> {
+    pub node: NodeRef<'s, T>,
+    pub parent: &'a [u8],
+    pub depth: usize,
+    pub ty: EntryType,
+}
+
+/// Iterates over every node of a trie, one sibling group at a time, lending
+/// the full key of each group's parent from one shared buffer.
+///
+/// Like [`Stream`], this implements [`Streamer`] rather than [`Iterator`].
+pub struct RawStream<'a, T: Integer> {
+    /// The rest of the current sibling group, in reverse.
+    group: Vec<NodeRef<'a, T>>,
     depth: usize,
+    /// The full key of the current group's parent.
+    key: Vec<u8>,
+    /// Nodes whose children are still to be yielded, with the length of their
+    /// parent's key and their depth.
+    stack: Vec<(NodeRef<'a, T>, usize, usize)>,
 }
 
-impl<'a, T: Integer> Iterator for RawEntries<'a, T> {
-    type Item = RawEntry<'a, T>;
+impl<'a, 's, T: Integer + 's> Streamer<'a> for RawStream<'s, T>
+where
+    's: 'a,
+{
+    type Item = RawEntryRef<'a, 's, T>;
+
+    fn next(&'a mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(node) = self.group.pop() {
+                return Some(RawEntryRef {
+                    node,
+                    parent: &self.key,
+                    depth: self.depth,
+                    ty: EntryType::Child,
+                });
+            }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        match &self.node.body {
-            NodeBody::Children(children) => {
-                if self.cur < children.len() {
-                    let parent = [&*self.parent, &*self.node.key].concat().into_boxed_slice();
-                    let result = Some(RawEntry {
-                        node: &children[self.cur],
-                        parent,
-                        depth: self.depth,
-                        ty: EntryType::Child,
-                    });
-                    self.cur += 1;
-                    return result;
-                }
+            let (node, parent_len, depth) = self.stack.pop()?;
+            self.key.truncate(parent_len);
+            self.key.extend_from_slice(node.key());
 
-                loop {
-                    if self.child_cur >= children.len() {
-                        return None;
-                    }
+            self.group.extend(node.children());
+            self.group.reverse();
 
-                    if self.child.is_none() {
-                        let parent = [&*self.parent, &*self.node.key].concat().into_boxed_slice();
-                        self.child = Some(Box::new(RawEntries::new(
-                            &children[self.child_cur],
-                            parent,
-                            self.depth + 1,
-                        )));
-                    }
+            // Each group is followed by the groups below it, first child first.
+            let key_len = self.key.len();
+            self.stack.extend(
+                self.group
+                    .iter()
+                    .filter(|x| matches!(x.body(), NodeBody::Children(_)))
+                    .map(|x| (*x, key_len, depth + 1)),
+            );
 
-                    if let Some(value) = self.child.as_mut().unwrap().next() {
-                        return Some(value);
-                    } else {
-                        self.child_cur += 1;
-                        self.child = None;
-                    }
-                }
-            }
-            NodeBody::Value(_) => None,
+            self.depth = depth;
         }
     }
 }
 
-pub struct Entries<'a, T: Integer> {
-    node: &'a Node<T>,
-    cur: usize,
-    child: Option<Box<Entries<'a, T>>>,
+/// Iterates over every node of a trie like [`RawStream`], copying out each
+/// parent key.
+pub struct RawEntries<'a, T: Integer>(RawStream<'a, T>);
+
+impl<'a, T: Integer> Iterator for RawEntries<'a, T> {
+    type Item = RawEntry<'a, T>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|entry| RawEntry {
+            node: entry.node,
+            parent: entry.parent.into(),
+            depth: entry.depth,
+            ty: entry.ty,
+        })
+    }
+}
+
+/// Iterates over a trie's entries in key order, reusing one buffer for every key.
+///
+/// This is a lending iterator, so it implements [`Streamer`] rather than
+/// [`Iterator`].
+pub struct Stream<'a, T: Integer> {
+    key: Vec<u8>,
+    stack: Vec<(NodeRef<'a, T>, usize)>,
 }
 
-impl<'a, T: Integer> Entries<'a, T> {
-    #[inline(always)]
-    fn new(node: &'a Node<T>) -> Self {
-        Self {
-            node,
-            cur: 0,
-            child: None,
+impl<'a, 's, T: Integer + 's> Streamer<'a> for Stream<'s, T>
+where
+    's: 'a,
+{
+    type Item = (&'a [u8], &'s T);
+
+    fn next(&'a mut self) -> Option<Self::Item> {
+        loop {
+            let (node, depth) = self.stack.pop()?;
+            self.key.truncate(depth);
+            self.key.extend_from_slice(node.key());
+
+            match node.body() {
+                NodeBody::Children(_) => {
+                    let depth = self.key.len();
+                    let start = self.stack.len();
+                    self.stack.extend(node.children().map(|x| (x, depth)));
+                    self.stack[start..].reverse();
+                }
+                NodeBody::Value(value) => return Some((&self.key, value)),
+            }
         }
     }
 }
 
+pub struct Entries<'a, T: Integer>(Stream<'a, T>);
+
 impl<'a, T: Integer> Iterator for Entries<'a, T> {
     type Item = (Box<[u8]>, &'a T);
 
+    #[inline]
     fn next(&mut self) -> Option<Self::Item> {
-        match &self.node.body {
-            NodeBody::Children(children) => loop {
-                if self.cur >= children.len() {
-                    return None;
-                }
+        self.0.next().map(|(key, value)| (key.into(), value))
+    }
+}
 
-                if self.child.is_none() {
-                    self.child = Some(Box::new(Entries::new(&children[self.cur])));
-                }
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PathTrie<T: Integer, A: Aggregate<T> = ()> {
+    pub(crate) arena: Arena<T>,
+    /// The aggregate of each node, by id.
+    #[cfg_attr(
+        feature = "serde",
+        serde(bound(
+            serialize = "A::Value: serde::Serialize",
+            deserialize = "A::Value: serde::Deserialize<'de>"
+        ))
+    )]
+    aggregates: Vec<A::Value>,
+    aggregate: PhantomData<fn() -> A>,
+}
 
-                if let Some(value) = self.child.as_mut().unwrap().next() {
-                    let mut out = self.node.key.to_vec();
-                    out.append(&mut value.0.to_vec());
-                    return Some((out.into_boxed_slice(), value.1));
-                } else {
-                    self.cur += 1;
-                    self.child = None;
-                }
-            },
-            NodeBody::Value(value) => {
-                if self.cur > 0 {
-                    return None;
-                }
-                self.cur += 1;
-                Some((self.node.key.to_vec().into_boxed_slice(), value))
-            }
-        }
+impl<T: Integer, A: Aggregate<T>> Debug for PathTrie<T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PathTrie")
+            .field("root", &self.root())
+            .finish()
     }
 }
 
-#[derive(Debug)]
-#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
-pub struct PathTrie<T: Integer> {
-    root: Node<T>,
+impl<T: Integer, A: Aggregate<T>> Display for PathTrie<T, A> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.tree().fmt(f)
+    }
 }
 
-impl<T: Integer> Default for PathTrie<T> {
+impl<T: Integer, A: Aggregate<T>> Default for PathTrie<T, A> {
     fn default() -> Self {
-        PathTrie::new()
+        Self {
+            arena: Arena::new(),
+            aggregates: vec![A::empty()],
+            aggregate: PhantomData,
+        }
     }
 }
 
 impl<T: Integer> PathTrie<T> {
     #[inline]
     pub fn new() -> Self {
-        Self {
-            root: Node {
-                key: vec![].into_boxed_slice(),
-                body: NodeBody::Children(vec![]),
-            },
-        }
+        Self::default()
+    }
+}
+
+impl<T: Integer, A: Aggregate<T>> PathTrie<T, A> {
+    #[inline]
+    pub(crate) fn root(&self) -> NodeRef<'_, T> {
+        self.arena.node_ref(ROOT)
     }
 
     #[inline]
@@ -176,266 +224,850 @@ impl<T: Integer> PathTrie<T> {
 
     #[inline]
     pub fn entries(&self) -> Entries<'_, T> {
-        Entries::new(&self.root)
+        Entries(self.stream())
+    }
+
+    #[inline]
+    pub fn stream(&self) -> Stream<'_, T> {
+        Stream {
+            key: vec![],
+            stack: vec![(self.root(), 0)],
+        }
+    }
+
+    /// Like [`PathTrie::stream`], but only over keys starting with `prefix`.
+    #[cfg(feature = "std")]
+    pub(crate) fn stream_prefix(&self, prefix: &[u8]) -> Stream<'_, T> {
+        let mut rest = prefix;
+        let mut key = vec![];
+        let mut node = ROOT;
+
+        while !rest.is_empty() {
+            match self.find_prefix(rest, node) {
+                (Some(child), Prefix::Incomplete(partial)) => {
+                    key.extend_from_slice(&rest[..partial]);
+                    rest = &rest[partial..];
+                    node = child;
+                }
+                (Some(child), Prefix::Exact) | (Some(child), Prefix::PerfectSubset(_)) => {
+                    node = child;
+                    break;
+                }
+                _ => return Stream { key, stack: vec![] },
+            }
+        }
+
+        let depth = key.len();
+        Stream {
+            key,
+            stack: vec![(self.arena.node_ref(node), depth)],
+        }
     }
 
+    /// Returns a [`Tree`] to draw the trie's keys as a directory tree.
+    #[inline]
+    pub fn tree(&self) -> Tree<'_, T, A> {
+        Tree::new(self)
+    }
+
+    #[inline]
     pub fn raw_entries(&self) -> RawEntries<'_, T> {
-        RawEntries::new(&self.root, Default::default(), 0)
+        RawEntries(self.raw_stream())
+    }
+
+    pub fn raw_stream(&self) -> RawStream<'_, T> {
+        RawStream {
+            group: vec![],
+            depth: 0,
+            key: vec![],
+            stack: vec![(self.root(), 0, 0)],
+        }
     }
 
     #[inline]
     pub fn insert<K: AsRef<[u8]>>(&mut self, key: K, value: T) {
         let key = key.as_ref();
-        Self::insert_inner(&mut self.root, key, value)
+        let fresh = self.arena.len();
+        self.insert_inner(ROOT, key, value);
+        self.update(key, fresh);
     }
 
-    #[inline]
-    fn get_node<K: AsRef<[u8]>>(&self, key: K) -> Option<&Node<T>> {
+    pub fn remove<K: AsRef<[u8]>>(&mut self, key: K) -> Option<T> {
         let key = key.as_ref();
+        let mut path = vec![];
+        if !self.trace(key, &mut path) {
+            return None;
+        }
+
+        let node = path.pop().unwrap();
+        let parent = *path.last().unwrap();
+        let value = self.arena.body(node).assert_value();
+
+        self.arena.remove(parent, node);
+        if parent != ROOT {
+            // Every node but the root has at least two children, so at most
+            // one is left here.
+            self.arena.compact(parent);
+        }
+
+        self.update_path(&path, self.arena.len());
+
+        // Live nodes never outnumber twice the values, so past this most of
+        // the arena is left over from removals.
+        if self.arena.len() > 4 * self.len() + 64 {
+            self.shrink_to_fit();
+        }
+
+        Some(value)
+    }
 
-        self.walk(key, &self.root)
+    /// Rebuilds the trie's storage without the nodes and key bytes left
+    /// behind by [`PathTrie::remove`]. This happens on its own once they make
+    /// up most of it.
+    pub fn shrink_to_fit(&mut self) {
+        self.arena = self.arena.rebuild();
+        self.aggregates = vec![];
+        self.update_all();
+        self.aggregates.shrink_to_fit();
     }
 
     #[inline]
-    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Option<T> {
-        self.get_node(key).and_then(|node| match &node.body {
-            NodeBody::Children(children) => children
-                .get(0)
-                .filter(|x| x.key.is_empty())
-                .map(|v| v.body.assert_value()),
-            NodeBody::Value(v) => Some(*v),
-        })
+    pub fn len(&self) -> usize {
+        self.arena.count(ROOT) as usize
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 
-    fn insert_inner(node: &mut Node<T>, key: &[u8], value: T) {
-        let (result, prefix) = Self::find_prefix(key, &node);
+    /// Returns the number of keys that sort before `key`, whether or not `key`
+    /// is in the trie.
+    pub fn rank<K: AsRef<[u8]>>(&self, key: K) -> usize {
+        let mut key = key.as_ref();
+        let mut node = ROOT;
+        let mut rank = 0;
+
+        while let Some(children) = self.arena.children(node) {
+            // Only the empty key, which is `key` itself, could follow here.
+            let first = match key.first() {
+                Some(first) => *first,
+                None => break,
+            };
 
-        match (result, prefix) {
-            (None, Prefix::NoMatch(_)) => {
-                node.push(Node {
-                    key: key.to_vec().into_boxed_slice(),
-                    body: NodeBody::Value(value),
-                });
+            let mut next = None;
+            for child in children.iter() {
+                match self.arena.key(child).first() {
+                    Some(byte) if *byte == first => next = Some(child),
+                    Some(byte) if *byte > first => break,
+                    _ => rank += self.arena.count(child) as usize,
+                }
             }
-            (Some(_), Prefix::Incomplete(0)) => {
-                node.push(Node {
-                    key: key.to_vec().into_boxed_slice(),
-                    body: NodeBody::Value(value),
-                });
+
+            let child = match next {
+                Some(child) => child,
+                None => break,
+            };
+
+            let child_key = self.arena.key(child);
+            match find_common_prefix(child_key, key) {
+                // A stored key that `key` runs past sorts before it.
+                Prefix::Incomplete(_) if self.arena.children(child).is_none() => {
+                    rank += 1;
+                    break;
+                }
+                Prefix::Incomplete(partial) => key = &key[partial..],
+                Prefix::Exact => key = &[],
+                Prefix::Divergent(partial) if child_key[partial] < key[partial] => {
+                    rank += self.arena.count(child) as usize;
+                    break;
+                }
+                _ => break,
+            }
+
+            node = child;
+        }
+
+        rank
+    }
+
+    /// Returns the entry at position `index` in key order.
+    pub fn select(&self, mut index: usize) -> Option<(Box<[u8]>, &T)> {
+        if index >= self.len() {
+            return None;
+        }
+
+        let mut key = vec![];
+        let mut node = ROOT;
+
+        loop {
+            let children = match self.arena.body(node) {
+                NodeBody::Children(children) => children,
+                NodeBody::Value(value) => return Some((key.into_boxed_slice(), value)),
+            };
+
+            for child in children.iter() {
+                let count = self.arena.count(child) as usize;
+                if index < count {
+                    key.extend_from_slice(self.arena.key(child));
+                    node = child;
+                    break;
+                }
+                index -= count;
+            }
+        }
+    }
+
+    /// Returns the aggregate of every value whose key starts with `prefix`.
+    pub fn aggregate_prefix<K: AsRef<[u8]>>(&self, prefix: K) -> A::Value {
+        let mut prefix = prefix.as_ref();
+        let mut node = ROOT;
+
+        while !prefix.is_empty() {
+            match self.find_prefix(prefix, node) {
+                (Some(child), Prefix::Incomplete(partial)) => {
+                    prefix = &prefix[partial..];
+                    node = child;
+                }
+                (Some(child), Prefix::Exact) | (Some(child), Prefix::PerfectSubset(_)) => {
+                    node = child;
+                    break;
+                }
+                _ => return A::empty(),
+            }
+        }
+
+        self.aggregates[node as usize].clone()
+    }
+
+    /// Collects the nodes on the way to `key`, ending with the first node that
+    /// doesn't lie wholly within it. Returns whether that node holds the value
+    /// for `key`.
+    fn trace(&self, mut key: &[u8], path: &mut Vec<NodeId>) -> bool {
+        let mut node = ROOT;
+        path.push(node);
+
+        loop {
+            let (result, prefix) = self.find_prefix(key, node);
+            let child = match result {
+                Some(child) => child,
+                None => return false,
+            };
+            path.push(child);
+
+            match (prefix, self.arena.body(child)) {
+                (Prefix::Exact, NodeBody::Value(_)) => return true,
+                (Prefix::Exact, NodeBody::Children(_)) => key = &[],
+                (Prefix::Incomplete(partial), NodeBody::Children(_)) => key = &key[partial..],
+                _ => return false,
+            }
+
+            node = child;
+        }
+    }
+
+    /// Brings the aggregates up to date after a change to `key`, given the
+    /// number of nodes in the arena before the change.
+    fn update(&mut self, key: &[u8], fresh: usize) {
+        let mut path = vec![];
+        self.trace(key, &mut path);
+        self.update_path(&path, fresh);
+    }
+
+    fn update_path(&mut self, path: &[NodeId], fresh: usize) {
+        self.aggregates.resize(self.arena.len(), A::empty());
+
+        // Nodes made by splitting the path hang off it, so they're done first.
+        for id in (fresh..self.arena.len()).rev() {
+            self.refresh(id as NodeId);
+        }
+
+        for id in path.iter().rev() {
+            self.refresh(*id);
+        }
+    }
+
+    /// Recomputes every count and aggregate in the trie.
+    pub(crate) fn update_all(&mut self) {
+        self.aggregates.resize(self.arena.len(), A::empty());
+
+        // Splits move children under newer nodes, so ids can't be relied on
+        // for order, and children are done first by walking the trie instead.
+        let mut stack = vec![(ROOT, false)];
+        while let Some((id, visited)) = stack.pop() {
+            match self.arena.children(id) {
+                Some(children) if !visited => {
+                    stack.push((id, true));
+                    stack.extend(children.iter().map(|child| (child, false)));
+                }
+                _ => self.refresh(id),
+            }
+        }
+    }
+
+    /// Updates the count and aggregate of `id` from its children.
+    #[inline]
+    fn refresh(&mut self, id: NodeId) {
+        self.arena.recount(id);
+
+        if size_of::<A::Value>() != 0 {
+            self.aggregates[id as usize] = self.compute(id);
+        }
+    }
+
+    #[inline]
+    fn compute(&self, id: NodeId) -> A::Value {
+        match self.arena.body(id) {
+            NodeBody::Value(value) => A::value(value),
+            NodeBody::Children(children) => children.iter().fold(A::empty(), |acc, child| {
+                A::combine(&acc, &self.aggregates[child as usize])
+            }),
+        }
+    }
+
+    /// Builds a trie from keys in ascending order in a single pass.
+    ///
+    /// Each key is attached to the path of the key before it, so sorted input
+    /// never searches from the root. Keys that arrive out of order are still
+    /// inserted, just at the cost of a normal [`PathTrie::insert`].
+    pub fn from_sorted_iter<I, K>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = (K, T)>,
+        K: AsRef<[u8]>,
+    {
+        let mut trie = Self::default();
+        // The largest key so far, and the nodes on its path with the length of
+        // the key at the end of each.
+        let mut last = Vec::new();
+        let mut path = Vec::new();
+
+        for (key, value) in iter {
+            let key = key.as_ref();
+
+            if !path.is_empty() && key > &*last {
+                trie.append(&mut path, &last, key, value);
+                last.clear();
+                last.extend_from_slice(key);
+                continue;
             }
-            (Some(i), Prefix::Incomplete(partial)) => {
-                // Keep walking.
-                Self::insert_inner(node.get_mut(i), &key[partial..], value);
+
+            trie.insert_inner(ROOT, key, value);
+            if path.is_empty() {
+                last.extend_from_slice(key);
             }
-            (Some(i), Prefix::PerfectSubset(partial)) => {
-                let prefix_key = node.get_mut(i).key[..partial].to_vec().into_boxed_slice();
-                let key = node.get_mut(i).key[partial..].to_vec().into_boxed_slice();
+            // The insert may have split nodes on the path, so find it again.
+            trie.rightmost_path(&last, &mut path);
+        }
+
+        trie.update_all();
+        trie
+    }
 
-                node.get_mut(i).key = prefix_key;
-                let mut value = NodeBody::Value(value);
-                std::mem::swap(&mut value, &mut node.get_mut(i).body);
+    /// Adds `key`, which must sort after `last`, below the path to `last`.
+    fn append(&mut self, path: &mut Vec<(NodeId, usize)>, last: &[u8], key: &[u8], value: T) {
+        let common = last.iter().zip(key).take_while(|(a, b)| a == b).count();
 
-                node.get_mut(i).push(Node { key, body: value });
+        let mut diverged = None;
+        while let Some(&(id, end)) = path.last() {
+            if end <= common {
+                break;
             }
-            (Some(i), Prefix::Divergent(partial)) => {
-                node.diverge(i, partial, key, value);
+            diverged = path.pop().map(|_| id);
+        }
+
+        let (mut parent, start) = *path.last().unwrap();
+        if let Some(id) = diverged {
+            if start < common {
+                self.arena.split(id, common - start);
+                path.push((id, common));
+                parent = id;
             }
-            (Some(i), Prefix::Exact) => {
-                node.get_mut(i).set_value(value);
+        }
+
+        let child = self.arena.alloc(&key[common..], NodeBody::Value(value));
+        self.arena.push(parent, child);
+        path.push((child, key.len()));
+    }
+
+    fn rightmost_path(&self, key: &[u8], path: &mut Vec<(NodeId, usize)>) {
+        path.clear();
+        path.push((ROOT, 0));
+
+        let (mut node, mut end) = (ROOT, 0);
+        loop {
+            match self.find_prefix(&key[end..], node) {
+                // An empty key holds its parent's value, and nothing can be added below it.
+                (Some(child), Prefix::Exact) if self.arena.key(child).is_empty() => return,
+                (Some(child), Prefix::Exact) => {
+                    path.push((child, key.len()));
+                    return;
+                }
+                (Some(child), Prefix::Incomplete(partial)) => {
+                    end += partial;
+                    path.push((child, end));
+                    node = child;
+                }
+                _ => return,
             }
-            _ => unreachable!(),
         }
     }
 
     #[inline]
-    fn find_prefix<'a>(key: &[u8], node: &'a Node<T>) -> (Option<usize>, Prefix) {
-        let mut prefix: Prefix = Prefix::NoMatch(Ordering::Equal);
-        let result = match &node.body {
-            NodeBody::Children(children) => children.iter().position(|x| {
-                prefix = find_common_prefix(&x.key, &key);
-                match prefix {
-                    Prefix::NoMatch(_) => false,
-                    _ => true,
+    fn get_node<K: AsRef<[u8]>>(&self, key: K) -> Option<NodeId> {
+        let key = key.as_ref();
+
+        self.walk(key, ROOT)
+    }
+
+    #[inline]
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Option<T> {
+        self.get_node(key)
+            .and_then(|node| match self.arena.body(node) {
+                NodeBody::Children(children) => {
+                    children.end().map(|v| self.arena.body(v).assert_value())
                 }
-            }),
-            NodeBody::Value(_) => None,
+                NodeBody::Value(v) => Some(*v),
+            })
+    }
+
+    fn insert_inner(&mut self, mut node: NodeId, mut key: &[u8], value: T) {
+        loop {
+            let (result, prefix) = self.find_prefix(key, node);
+
+            match (result, prefix) {
+                (None, Prefix::NoMatch(_)) => {
+                    let child = self.arena.alloc(key, NodeBody::Value(value));
+                    self.arena.push(node, child);
+                }
+                (Some(child), Prefix::Incomplete(partial)) => {
+                    // Keep walking.
+                    node = child;
+                    key = &key[partial..];
+                    continue;
+                }
+                (Some(child), Prefix::PerfectSubset(partial)) => {
+                    self.arena.split(child, partial);
+
+                    let value = self.arena.alloc(&[], NodeBody::Value(value));
+                    self.arena.push(child, value);
+                }
+                (Some(child), Prefix::Divergent(partial)) => {
+                    self.arena.diverge(child, partial, key, value);
+                }
+                (Some(child), Prefix::Exact) => match self.arena.body(child) {
+                    NodeBody::Value(_) => self.arena.set_value(child, value),
+                    NodeBody::Children(_) => {
+                        node = child;
+                        key = &[];
+                        continue;
+                    }
+                },
+                _ => unreachable!(),
+            }
+
+            return;
+        }
+    }
+
+    /// Finds the child of `node` that shares a prefix with `key`, if any.
+    #[inline]
+    fn find_prefix(&self, key: &[u8], node: NodeId) -> (Option<NodeId>, Prefix) {
+        let children = match self.arena.body(node) {
+            NodeBody::Children(children) => children,
+            NodeBody::Value(_) => return (None, Prefix::NoMatch(Ordering::Equal)),
+        };
+
+        let child = match key.first() {
+            // The empty key holds the parent's own value and only matches itself.
+            None => children.end(),
+            Some(byte) => children.find(*byte),
         };
 
-        (result, prefix)
+        match child {
+            Some(child) => (Some(child), find_common_prefix(self.arena.key(child), key)),
+            None => (None, Prefix::NoMatch(Ordering::Equal)),
+        }
     }
 
-    fn walk<'a>(&'a self, key: &[u8], node: &'a Node<T>) -> Option<&Node<T>> {
-        let (result, prefix) = Self::find_prefix(key, node);
+    fn walk(&self, mut key: &[u8], mut node: NodeId) -> Option<NodeId> {
+        loop {
+            let (result, prefix) = self.find_prefix(key, node);
 
-        match (result, prefix, &node.body) {
-            (Some(i), Prefix::Exact, NodeBody::Children(children)) => match &children[i].body {
-                NodeBody::Children(children) => children.last().filter(|x| x.key.is_empty()),
-                NodeBody::Value(_) => Some(&children[i]),
-            },
-            (Some(i), Prefix::Incomplete(partial), NodeBody::Children(children)) => {
-                self.walk(&key[partial..], &children[i])
+            match (result, prefix) {
+                (Some(child), Prefix::Exact) => {
+                    return match self.arena.body(child) {
+                        NodeBody::Children(children) => children.end(),
+                        NodeBody::Value(_) => Some(child),
+                    }
+                }
+                (Some(child), Prefix::Incomplete(partial)) => {
+                    key = &key[partial..];
+                    node = child;
+                }
+                (_, Prefix::NoMatch(_)) => return None,
+                (_, Prefix::Divergent(_)) => return None,
+                (_, Prefix::PerfectSubset(_)) => return None,
+                unexpected => unreachable!("{:?}", unexpected),
             }
-            (_, Prefix::NoMatch(_), _) => None,
-            (_, Prefix::Divergent(_), _) => None,
-            (_, Prefix::PerfectSubset(_), _) => None,
-            unexpected => unreachable!("{:?}", unexpected),
         }
     }
+
+    /// Merges `other` into this trie. Subtrees that only exist in `other` are
+    /// copied across whole; `resolve` is called with the key and both values
+    /// for every key present in both tries.
+    pub fn merge<F>(&mut self, other: PathTrie<T, A>, mut resolve: F)
+    where
+        F: FnMut(&[u8], T, T) -> T,
+    {
+        let other = &other.arena;
+        let mut key = Vec::new();
+
+        // Each item carries the length of `key` it starts from, since a
+        // sibling's bytes may still be on the end of it.
+        let mut stack = vec![Merge::Node {
+            ours: ROOT,
+            theirs: ROOT,
+            key_len: 0,
+        }];
+
+        while let Some(item) = stack.pop() {
+            match item {
+                Merge::Node {
+                    ours,
+                    theirs,
+                    key_len,
+                } => {
+                    key.truncate(key_len);
+
+                    match (self.arena.body(ours), other.body(theirs)) {
+                        (NodeBody::Value(ours_value), NodeBody::Value(theirs)) => {
+                            let value = resolve(&key, *ours_value, *theirs);
+                            self.arena.set_value(ours, value);
+                        }
+                        (NodeBody::Children(_), NodeBody::Value(_)) => {
+                            // Their whole key has been consumed, leaving only
+                            // the value.
+                            stack.push(Merge::Child {
+                                ours,
+                                child: theirs,
+                                skip: other.key(theirs).len(),
+                                key_len,
+                            });
+                        }
+                        (_, NodeBody::Children(children)) => {
+                            if let NodeBody::Value(_) = self.arena.body(ours) {
+                                self.arena.convert_value_to_children(ours, 0);
+                            }
+
+                            // Reversed, so children are merged in key order.
+                            let len = stack.len();
+                            stack.extend(children.iter().map(|child| Merge::Child {
+                                ours,
+                                child,
+                                skip: 0,
+                                key_len,
+                            }));
+                            stack[len..].reverse();
+                        }
+                    }
+                }
+                Merge::Child {
+                    ours,
+                    child,
+                    skip,
+                    key_len,
+                } => {
+                    key.truncate(key_len);
+                    self.merge_child(ours, other, child, skip, &mut key, &mut stack);
+                }
+            }
+        }
+
+        self.update_all();
+    }
+
+    /// Merges their `child` into our `node`, ignoring the first `skip` bytes of
+    /// its key. Pairs of nodes with the same key are pushed onto `stack`.
+    fn merge_child(
+        &mut self,
+        mut node: NodeId,
+        other: &Arena<T>,
+        child: NodeId,
+        mut skip: usize,
+        key: &mut Vec<u8>,
+        stack: &mut Vec<Merge>,
+    ) {
+        loop {
+            let child_key = &other.key(child)[skip..];
+
+            match self.find_prefix(child_key, node) {
+                (None, Prefix::NoMatch(_)) => {
+                    let id = self.import(other, child, skip);
+                    self.arena.push(node, id);
+                }
+                (Some(ours), Prefix::Exact) => {
+                    key.extend_from_slice(child_key);
+                    stack.push(Merge::Node {
+                        ours,
+                        theirs: child,
+                        key_len: key.len(),
+                    });
+                }
+                (Some(ours), Prefix::Incomplete(partial)) => {
+                    // Our key is a prefix of theirs, so keep walking down ours.
+                    key.extend_from_slice(&child_key[..partial]);
+
+                    if let NodeBody::Value(_) = self.arena.body(ours) {
+                        self.arena.convert_value_to_children(ours, 0);
+                    }
+                    node = ours;
+                    skip += partial;
+                    continue;
+                }
+                (Some(ours), Prefix::PerfectSubset(partial)) => {
+                    // Their key is a prefix of ours, so split ours to line them up.
+                    self.arena.split(ours, partial);
+                    key.extend_from_slice(child_key);
+                    stack.push(Merge::Node {
+                        ours,
+                        theirs: child,
+                        key_len: key.len(),
+                    });
+                }
+                (Some(ours), Prefix::Divergent(partial)) => {
+                    self.arena.split(ours, partial);
+                    let id = self.import(other, child, skip + partial);
+                    self.arena.push(ours, id);
+                }
+                _ => unreachable!(),
+            }
+
+            return;
+        }
+    }
+
+    /// Copies the subtree at `id` in `other` into this trie, without the first
+    /// `skip` bytes of its key.
+    fn import(&mut self, other: &Arena<T>, id: NodeId, skip: usize) -> NodeId {
+        let root = self.import_node(other, id, skip);
+
+        let mut stack = vec![(root, id)];
+        while let Some((ours, theirs)) = stack.pop() {
+            for child in other.children(theirs).into_iter().flat_map(|x| x.iter()) {
+                let id = self.import_node(other, child, 0);
+                self.arena.push(ours, id);
+                stack.push((id, child));
+            }
+        }
+
+        root
+    }
+
+    #[inline]
+    fn import_node(&mut self, other: &Arena<T>, id: NodeId, skip: usize) -> NodeId {
+        let body = match other.body(id) {
+            NodeBody::Value(value) => NodeBody::Value(*value),
+            NodeBody::Children(_) => NodeBody::Children(Children::default()),
+        };
+        self.arena.alloc(&other.key(id)[skip..], body)
+    }
 }
 
-const HEADER_SIZE: usize = size_of::<fst::Header>();
+/// Work left over while merging one trie into another.
+enum Merge {
+    /// Merge their node into ours, both of which end at the same key.
+    Node {
+        ours: NodeId,
+        theirs: NodeId,
+        key_len: usize,
+    },
+    /// Merge their child, less `skip` bytes of its key, into our node.
+    Child {
+        ours: NodeId,
+        child: NodeId,
+        skip: usize,
+        key_len: usize,
+    },
+}
 
-impl<T: Integer> PathTrie<T> {
-    const NODE_SIZE: usize = size_of::<fst::Node<T>>();
-    const VERSION: u8 = 0;
-    const ALIGNMENT: u8 = size_of::<T>() as u8;
-
-    pub fn write_fst<W: Write + Seek>(&self, writer: &mut W) -> Result<(), bare_io::Error> {
-        let starting_offset = writer.seek(SeekFrom::Current(0))?;
-        let mut current_parent: Box<[u8]> = Default::default();
-        let node_zero_buf = vec![0; Self::NODE_SIZE].into_boxed_slice();
-
-        let mut wip_offsets = BTreeMap::new();
-
-        // Write a blank header
-        writer.write_all(&[0u8; HEADER_SIZE])?;
-        // Write alignment nulls
-        let current_pos = writer.seek(SeekFrom::Current(0))?;
-        let bytes_until_aligned = current_pos % Self::ALIGNMENT as u64;
-        writer.write_all(&vec![0u8; bytes_until_aligned as usize])?;
-
-        // Write nodes innit
-        for entry in self.raw_entries() {
+/// Fills a slice from the start, as `Write` isn't implemented for `&mut [u8]`
+/// without `std`. Space is checked up front, so writes never fall short.
+struct SliceWriter<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl Write for SliceWriter<'_> {
+    #[inline]
+    fn write(&mut self, bytes: &[u8]) -> Result<usize, bare_io::Error> {
+        let len = bytes.len().min(self.buf.len() - self.pos);
+        self.buf[self.pos..self.pos + len].copy_from_slice(&bytes[..len]);
+        self.pos += len;
+        Ok(len)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> Result<(), bare_io::Error> {
+        Ok(())
+    }
+}
+
+impl<T: Integer, A: Aggregate<T>> PathTrie<T, A> {
+    /// Writes an [`Fst`](crate::Fst) with `u32` offsets, which is enough for
+    /// up to 4 GiB.
+    #[inline]
+    pub fn write_fst<W: Write>(&self, writer: &mut W) -> Result<(), fst::WriteError> {
+        self.write_fst_with_offsets::<u32, W>(writer)
+    }
+
+    /// Returns the exact number of bytes [`write_fst`](Self::write_fst) writes.
+    #[inline]
+    pub fn fst_size(&self) -> usize {
+        self.fst_layout::<T, u32, _>(|_, _| {}) as usize
+    }
+
+    /// Writes the same bytes as [`write_fst`](Self::write_fst) to the start
+    /// of `buf`, which must be at least [`fst_size`](Self::fst_size) long,
+    /// and returns how many were written.
+    pub fn write_fst_to_slice(&self, buf: &mut [u8]) -> Result<usize, fst::WriteError> {
+        let len = self.fst_size();
+        if buf.len() < len {
+            return Err(fst::WriteError::BufferTooSmall {
+                needed: len,
+                got: buf.len(),
+            });
+        }
+
+        self.write_fst(&mut SliceWriter { buf, pos: 0 })?;
+        Ok(len)
+    }
+
+    /// Writes an [`Fst`](crate::Fst) whose offsets between nodes are `O`s, to
+    /// be read back as an `Fst<T, O>`.
+    ///
+    /// Nothing is written until the whole layout is known, and the writer is
+    /// never seeked, so this works with pipes and compressors.
+    pub fn write_fst_with_offsets<O: fst::Offset, W: Write>(
+        &self,
+        writer: &mut W,
+    ) -> Result<(), fst::WriteError> {
+        let mut groups = BTreeMap::new();
+        let mut last = 0;
+        self.fst_layout::<T, O, _>(|parent, offset| {
+            groups.insert(Box::<[u8]>::from(parent), offset);
+            last = offset;
+        });
+
+        // Groups are laid out in order, so if the last fits, they all do.
+        if O::try_from(last).is_err() {
+            return Err(fst::WriteError::OffsetOverflow {
+                offset: last,
+                width: size_of::<O>(),
+            });
+        }
+
+        let alignment = fst::alignment::<T, O>();
+        let node_zero_buf = vec![0; alignment].into_boxed_slice();
+        let mut current_parent = Vec::new();
+        let mut full_key = Vec::new();
+
+        let mut pos = fst::start_offset::<T, O>();
+        writer.write_all(&[
+            b'\xff',
+            b'\xdf',
+            fst::VERSION,
+            size_of::<O>() as u8,
+            size_of::<T>() as u8,
+            0,
+            0,
+            0,
+        ])?;
+        writer.write_all(&vec![0u8; pos - fst::HEADER_SIZE])?;
+
+        let mut entries = self.raw_stream();
+        while let Some(entry) = entries.next() {
             let span = tracing::span!(
                 tracing::Level::TRACE,
                 "entry",
-                parent=%String::from_utf8_lossy(&*entry.parent),
-                key=%String::from_utf8_lossy(&*entry.node.key),
+                parent=%String::from_utf8_lossy(entry.parent),
+                key=%String::from_utf8_lossy(entry.node.key()),
             );
             let _guard = span.enter();
 
             if current_parent != entry.parent {
-                let span = tracing::span!(tracing::Level::TRACE, "new-parent",);
-                let _guard = span.enter();
-
-                // Write a blank entry
+                // End the previous group
                 writer.write_all(&node_zero_buf)?;
-                tracing::trace!(
-                    "Wrote empty entry, now at: {}",
-                    writer.seek(SeekFrom::Current(0))?
-                );
-                current_parent = entry.parent;
+                pos += alignment;
+                tracing::trace!("Wrote empty entry, now at: {}", pos);
+                current_parent.clear();
+                current_parent.extend_from_slice(entry.parent);
             }
 
-            let wip_key = current_parent
-                .iter()
-                .chain(entry.node.key.iter())
-                .copied()
-                .collect::<Vec<_>>()
-                .into_boxed_slice();
-            wip_offsets.insert(wip_key, writer.seek(SeekFrom::Current(0))?);
-
-            // Check parent in wips
-            if let Some(parent_offset) = wip_offsets.remove(&current_parent) {
-                let span = tracing::span!(tracing::Level::TRACE, "fill-wip-offset",);
-
-                let _guard = span.enter();
-                let current_offset = writer.seek(SeekFrom::Current(0))?;
-                tracing::trace!("Current offset: {}", current_offset);
-
-                // Go to offset and write current offset
-                writer.seek(SeekFrom::Start(parent_offset))?;
-                let offset =
-                    T::try_from(current_offset).unwrap_or_else(|_| panic!("Offset too large"));
-                offset.write_le_bytes(writer)?;
-                tracing::trace!(
-                    "Wrote current offset, now at: {}",
-                    writer.seek(SeekFrom::Current(0))?
-                );
-
-                // Return to current position
-                writer.seek(SeekFrom::Start(current_offset))?;
-                tracing::trace!(
-                    "Returned to 'current' offset at: {}",
-                    writer.seek(SeekFrom::Current(0))?
-                );
-            }
-
-            let span = tracing::span!(tracing::Level::TRACE, "children");
-            let _guard = span.enter();
-            let len: u8 = entry
-                .node
-                .key
-                .len()
-                .try_into()
-                .expect("Keys are currently limited to 255 bytes in length");
-
-            match &entry.node.body {
+            let key = entry.node.key();
+            match entry.node.body() {
                 NodeBody::Children(_) => {
-                    // Write zeros temporarily
-                    writer.write_all(&[0; size_of::<fst::NodeOffset>()])?;
-                    tracing::trace!(
-                        "Wrote empty WIP offset, now at: {}",
-                        writer.seek(SeekFrom::Current(0))?
-                    );
+                    full_key.clear();
+                    full_key.extend_from_slice(entry.parent);
+                    full_key.extend_from_slice(key);
+                    // Every offset was checked to fit above.
+                    let offset = O::try_from(groups[&*full_key]).unwrap_or_else(|_| unreachable!());
+                    offset.write_le_bytes(writer)?;
+                    pos += size_of::<O>();
                 }
-                NodeBody::Value(_) => {
-                    // Terminus sentinel
-                    writer.write_all(&[u8::MAX; size_of::<fst::NodeOffset>()])?;
-                    tracing::trace!(
-                        "Wrote terminus bytes, now at: {}",
-                        writer.seek(SeekFrom::Current(0))?
-                    );
+                NodeBody::Value(value) => {
+                    O::TERMINUS.write_le_bytes(writer)?;
+                    value.write_le_bytes(writer)?;
+                    pos += size_of::<O>() + size_of::<T>();
                 }
-            };
-
-            // Write value if present
-            if let NodeBody::Value(value) = entry.node.body {
-                value.write_le_bytes(writer)?;
-                tracing::trace!(
-                    "Wrote value, now at: {}",
-                    writer.seek(SeekFrom::Current(0))?
-                );
             }
 
-            // Write string with u8 size
-            writer.write_all(&[len])?;
-            tracing::trace!(
-                "Wrote len `{}`, now at: {}",
-                len,
-                writer.seek(SeekFrom::Current(0))?
-            );
-
-            writer.write_all(&entry.node.key)?;
-            tracing::trace!(
-                "Wrote `{}`, now at: {}",
-                String::from_utf8_lossy(&entry.node.key),
-                writer.seek(SeekFrom::Current(0))?
-            );
+            // Write string with varint size
+            fst::write_len(writer, key.len())?;
+            writer.write_all(key)?;
+            pos += fst::len_size(key.len()) + key.len();
 
             // Write zeros to ensure alignment
-            let current_pos = writer.seek(SeekFrom::Current(0))?;
-            let bytes_until_aligned = size_of::<T>() - current_pos as usize % size_of::<T>();
-            tracing::trace!("Writing {} padding bytes", bytes_until_aligned);
-            writer.write_all(&vec![0u8; bytes_until_aligned as usize])?;
-            tracing::trace!(
-                "Wrote padding, now at: {}",
-                writer.seek(SeekFrom::Current(0))?
-            );
-
-            assert!(writer.seek(SeekFrom::Current(0))? as usize % size_of::<T>() == 0);
+            let padding = fst::padding::<T, O>(pos);
+            writer.write_all(&node_zero_buf[..padding])?;
+            pos += padding;
+            tracing::trace!("Wrote node, now at: {}", pos);
         }
         writer.write_all(&node_zero_buf)?;
-        tracing::trace!(
-            "Wrote end of file padding, now at: {}",
-            writer.seek(SeekFrom::Current(0))?
-        );
 
-        // Seek back and write header
-        writer.seek(SeekFrom::Start(starting_offset))?;
-        writer.write_all(&[b'\xff', b'\xdf', Self::VERSION, Self::ALIGNMENT])?;
+        Ok(writer.flush()?)
+    }
 
-        writer.flush()
+    /// Lays the trie out as [`write_fst_with_offsets`](Self::write_fst_with_offsets)
+    /// would with values of type `U`, without writing anything. `group` is
+    /// called with the full key of each node with children and where they
+    /// start, and the total size is returned.
+    pub(crate) fn fst_layout<U: Integer, O: fst::Offset, F>(&self, mut group: F) -> u64
+    where
+        F: FnMut(&[u8], u64),
+    {
+        let alignment = fst::alignment::<U, O>();
+        let mut pos = fst::start_offset::<U, O>() as u64;
+        let mut current_parent = Vec::new();
+
+        let mut entries = self.raw_stream();
+        while let Some(entry) = entries.next() {
+            if current_parent != entry.parent {
+                pos += alignment as u64;
+                group(entry.parent, pos);
+                current_parent.clear();
+                current_parent.extend_from_slice(entry.parent);
+            }
+
+            let len = entry.node.key().len();
+            let mut size = size_of::<O>() + fst::len_size(len) + len;
+            if let NodeBody::Value(_) = entry.node.body() {
+                size += size_of::<U>();
+            }
+            pos += size as u64;
+            pos += fst::padding::<U, O>(pos as usize) as u64;
+        }
+
+        pos + alignment as u64
     }
 }
 
@@ -446,16 +1078,103 @@ mod tests {
     use std::fs::File;
     use std::io::Cursor;
 
+    #[test]
+    fn fst_siblings() {
+        let mut trie = PathTrie::<u32>::new();
+        for (n, key) in ["a/b", "a", "c"].iter().enumerate() {
+            trie.insert(key, n as u32);
+        }
+
+        let mut buf = Cursor::new(vec![]);
+        trie.write_fst(&mut buf).unwrap();
+        std::fs::write("./test-siblings.fst", buf.into_inner()).unwrap();
+        let mmap = unsafe { Mmap::map(&File::open("./test-siblings.fst").unwrap()).unwrap() };
+        let fst = fst::Fst::<u32>::new(&mmap).unwrap();
+
+        let roots = fst.roots().collect::<Vec<_>>();
+        assert_eq!(roots.len(), 2);
+        assert_eq!(roots[0].key(), b"a");
+        assert_eq!(roots[0].terminus(), None);
+        assert_eq!(roots[1].key(), b"c");
+        assert_eq!(roots[1].terminus(), Some(2));
+
+        let children = fst
+            .children(roots[0])
+            .map(|node| (node.key(), node.terminus()))
+            .collect::<Vec<_>>();
+        assert_eq!(children, vec![(&b""[..], Some(1)), (&b"/b"[..], Some(0))]);
+        assert_eq!(fst.children(roots[1]).count(), 0);
+    }
+
+    #[test]
+    fn lexicographic_order() {
+        let mut trie = PathTrie::<u32>::new();
+        let keys = ["b", "abc", "a", "ab", "a/b", "abd"];
+        for (n, key) in keys.iter().enumerate() {
+            trie.insert(key, n as u32);
+        }
+
+        let mut sorted = keys.to_vec();
+        sorted.sort();
+        let found = trie.keys().collect::<Vec<_>>();
+        let found = found
+            .iter()
+            .map(|key| std::str::from_utf8(key).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(found, sorted);
+
+        for (n, key) in keys.iter().enumerate() {
+            assert_eq!(trie.get(key), Some(n as u32), "{}", key);
+        }
+    }
+
+    #[test]
+    fn fst_prefix_keys() {
+        let mut trie = PathTrie::<u32>::new();
+        let keys = ["a/b/c", "a", "a/b", "ab", "a/bc"];
+        for (n, key) in keys.iter().enumerate() {
+            trie.insert(key, n as u32);
+        }
+
+        let mut buf = Cursor::new(vec![]);
+        trie.write_fst(&mut buf).unwrap();
+        std::fs::write("./test-prefix-keys.fst", buf.into_inner()).unwrap();
+        let mmap = unsafe { Mmap::map(&File::open("./test-prefix-keys.fst").unwrap()).unwrap() };
+        let fst = fst::Fst::<u32>::new(&mmap).unwrap();
+
+        for (n, key) in keys.iter().enumerate() {
+            assert_eq!(fst.get(key), Some(n as u32), "{}", key);
+        }
+        assert_eq!(fst.get("a/"), None);
+    }
+
+    #[test]
+    fn fst_get_past_terminus() {
+        let mut trie = PathTrie::<u32>::new();
+        trie.insert("ab", 1);
+        trie.insert("ac", 2);
+
+        let mut buf = Cursor::new(vec![]);
+        trie.write_fst(&mut buf).unwrap();
+        std::fs::write("./test-past-terminus.fst", buf.into_inner()).unwrap();
+        let mmap = unsafe { Mmap::map(&File::open("./test-past-terminus.fst").unwrap()).unwrap() };
+        let fst = fst::Fst::<u32>::new(&mmap).unwrap();
+
+        assert_eq!(fst.get("ab"), Some(1));
+        assert_eq!(fst.get("abc"), None);
+        assert_eq!(fst.get("acdc"), None);
+    }
+
     #[test]
     fn insert_subset() {
         let mut trie = PathTrie::<u32>::new();
 
         trie.insert("abcd", 1);
-        println!("{:?}", trie.root);
+        println!("{:?}", trie);
         trie.insert("ab", 2);
-        println!("{:?}", trie.root);
+        println!("{:?}", trie);
         trie.insert("abcdab", 3);
-        println!("{:?}", trie.root);
+        println!("{:?}", trie);
         assert_eq!(trie.keys().count(), 3);
     }
 
@@ -464,11 +1183,11 @@ mod tests {
         let mut trie = PathTrie::<u32>::new();
 
         trie.insert("ab/ano", 1);
-        println!("{:?}", trie.root);
+        println!("{:?}", trie);
         trie.insert("ab/bap", 2);
-        println!("{:?}", trie.root);
+        println!("{:?}", trie);
         trie.insert("ab/car", 3);
-        println!("{:?}", trie.root);
+        println!("{:?}", trie);
         trie.insert("abcdab", 4);
 
         assert_eq!(trie.keys().count(), 4);
@@ -495,11 +1214,11 @@ mod tests {
 
         for (n, path) in paths.iter().enumerate() {
             trie.insert(path, n as u32 + 1);
-            println!(":::{}::: {}", n, &trie.root);
+            println!(":::{}::: {}", n, trie.root());
             assert_eq!(trie.keys().count(), n + 1);
         }
 
-        println!(":::::: {}", trie.root);
+        println!(":::::: {}", trie.root());
 
         assert_eq!(trie.keys().count(), paths.len());
 
@@ -509,6 +1228,345 @@ mod tests {
         assert_eq!(trie.get("break-everything/haha"), Some(8));
     }
 
+    #[test]
+    fn insert_overwrite_with_children() {
+        let mut trie = PathTrie::<u32>::new();
+
+        trie.insert("ab/c", 1);
+        trie.insert("ab", 2);
+        trie.insert("ab", 3);
+        trie.insert("", 4);
+
+        assert_eq!(trie.get("ab/c"), Some(1));
+        assert_eq!(trie.get("ab"), Some(3));
+        assert_eq!(trie.get(""), Some(4));
+        assert_eq!(trie.keys().count(), 3);
+    }
+
+    #[test]
+    fn wide_fanout_in_key_order() {
+        let mut trie = PathTrie::<u32>::new();
+        let mut bytes = (0..=255u8).collect::<Vec<_>>();
+        bytes.reverse();
+        bytes.rotate_left(100);
+
+        for byte in bytes.iter() {
+            trie.insert([b'd', b'/', *byte], *byte as u32);
+        }
+        trie.insert("d", 1000);
+
+        let keys = trie.keys().collect::<Vec<_>>();
+        let mut sorted = keys.clone();
+        sorted.sort();
+        assert_eq!(keys, sorted);
+        assert_eq!(keys.len(), 257);
+
+        for byte in bytes.iter() {
+            assert_eq!(trie.get([b'd', b'/', *byte]), Some(*byte as u32));
+        }
+        assert_eq!(trie.get("d"), Some(1000));
+    }
+
+    #[test]
+    fn deep_paths() {
+        let mut trie = PathTrie::<u32>::new();
+        let mut path = String::new();
+
+        for n in 0..5_000u32 {
+            path.push_str(if n % 2 == 0 { "a/" } else { "b/" });
+            trie.insert(&path, n);
+        }
+
+        assert_eq!(trie.get(&path), Some(4_999));
+        assert_eq!(trie.get("a/b/a/"), Some(2));
+        assert_eq!(trie.keys().count(), 5_000);
+        assert_eq!(trie.values().copied().last(), Some(4_999));
+        assert!(trie.raw_entries().count() > 5_000);
+    }
+
+    #[test]
+    fn deep_merge() {
+        let deep = || {
+            let mut trie = PathTrie::<u32>::new();
+            let mut path = String::new();
+            for n in 0..5_000u32 {
+                path.push_str(if n % 2 == 0 { "a/" } else { "b/" });
+                trie.insert(&path, n);
+            }
+            (trie, path)
+        };
+
+        let (theirs, path) = deep();
+        let mut trie = PathTrie::<u32>::new();
+        trie.merge(theirs, |_, _, _| unreachable!());
+        assert_eq!(trie.get(&path), Some(4_999));
+        assert_eq!(trie.len(), 5_000);
+
+        let (theirs, _) = deep();
+        trie.merge(theirs, |_, ours, theirs| ours + theirs);
+        assert_eq!(trie.get(&path), Some(2 * 4_999));
+        assert_eq!(trie.get("a/b/a/"), Some(4));
+        assert_eq!(trie.len(), 5_000);
+    }
+
+    #[test]
+    fn stream() {
+        let mut trie = PathTrie::<u32>::new();
+
+        for (n, path) in ["b/c", "a", "b", "a/b/c", "ab"].iter().enumerate() {
+            trie.insert(path, n as u32);
+        }
+
+        let mut stream = trie.stream();
+        let mut keys = vec![];
+        while let Some((key, value)) = stream.next() {
+            keys.push((String::from_utf8_lossy(key).to_string(), *value));
+        }
+
+        let entries = trie
+            .entries()
+            .map(|(key, value)| (String::from_utf8_lossy(&key).to_string(), *value))
+            .collect::<Vec<_>>();
+        assert_eq!(keys, entries);
+        assert_eq!(keys[0], ("a".to_string(), 1));
+        assert_eq!(keys[4], ("b/c".to_string(), 0));
+    }
+
+    #[test]
+    fn from_sorted_iter() {
+        let mut paths = vec![
+            "bacon/sandwich/hamburger",
+            "bacon/sandwich/ham",
+            "bacon",
+            "bacon/sandwich/ham-replacement",
+            "bacon/baguette/croissant",
+            "bacon/jam",
+            "bacon/wat",
+            "break-everything/haha",
+            "",
+            "ba",
+        ];
+        paths.sort_unstable();
+
+        let trie = PathTrie::<u32>::from_sorted_iter(paths.iter().zip(1u32..));
+        let mut expected = PathTrie::<u32>::new();
+        for (path, n) in paths.iter().zip(1u32..) {
+            expected.insert(path, n);
+        }
+        assert_eq!(format!("{:?}", trie), format!("{:?}", expected));
+
+        // Out of order and repeated keys still end up in the trie.
+        let trie = PathTrie::<u32>::from_sorted_iter(vec![
+            ("b/c", 1u32),
+            ("b/d", 2),
+            ("a", 3),
+            ("b", 4),
+            ("b/d", 5),
+            ("b/da", 6),
+            ("c", 7),
+        ]);
+        assert_eq!(trie.keys().count(), 6);
+        assert_eq!(trie.get("a"), Some(3));
+        assert_eq!(trie.get("b"), Some(4));
+        assert_eq!(trie.get("b/d"), Some(5));
+        assert_eq!(trie.get("b/da"), Some(6));
+        assert_eq!(trie.get("c"), Some(7));
+    }
+
+    #[test]
+    fn remove() {
+        let paths = [
+            "bacon/sandwich/hamburger",
+            "bacon/sandwich/ham",
+            "bacon",
+            "bacon/sandwich/ham-replacement",
+            "bacon/baguette/croissant",
+            "bacon/jam",
+        ];
+
+        let mut trie = PathTrie::<u32>::new();
+        for (n, path) in paths.iter().enumerate() {
+            trie.insert(path, n as u32 + 1);
+        }
+
+        assert_eq!(trie.remove("bacon/sandwich"), None);
+        assert_eq!(trie.remove("bacon/jam/"), None);
+        assert_eq!(trie.remove("bacon/sandwich/ham"), Some(2));
+        assert_eq!(trie.remove("bacon/sandwich/ham"), None);
+        assert_eq!(trie.remove("bacon"), Some(3));
+        assert_eq!(trie.remove("bacon/jam"), Some(6));
+
+        // Removing leaves the same shape as never having inserted at all.
+        let mut expected = PathTrie::<u32>::new();
+        for (n, path) in paths.iter().enumerate() {
+            if [0, 3, 4].contains(&n) {
+                expected.insert(path, n as u32 + 1);
+            }
+        }
+        assert_eq!(format!("{:?}", trie), format!("{:?}", expected));
+
+        for path in paths.iter() {
+            trie.remove(path);
+        }
+        assert_eq!(trie.keys().count(), 0);
+        assert_eq!(trie.get("bacon/baguette/croissant"), None);
+    }
+
+    #[test]
+    fn remove_churn() {
+        let mut trie = PathTrie::<u32>::new();
+        for n in 0..1000u32 {
+            trie.insert(format!("dir/{}/file-{}", n % 10, n), n);
+        }
+
+        let mut most = (0, 0);
+        for round in 1..50u32 {
+            for n in 0..1000u32 {
+                let old = format!("dir/{}/file-{}", n % 10, (round - 1) * 1000 + n);
+                assert_eq!(trie.remove(old), Some(n));
+                trie.insert(format!("dir/{}/file-{}", n % 10, round * 1000 + n), n);
+            }
+
+            assert_eq!(trie.len(), 1000);
+            assert_eq!(trie.aggregates.len(), trie.arena.len());
+            most = (
+                most.0.max(trie.arena.len()),
+                most.1.max(trie.arena.key_bytes()),
+            );
+        }
+
+        // Without reclaiming, 50 rounds of 1000 removals leave far more behind.
+        assert!(most.0 <= 4 * 1000 + 64, "{:?}", most);
+        assert!(most.1 <= 20 * 1000, "{:?}", most);
+        assert_eq!(trie.get("dir/3/file-49003"), Some(3));
+        assert_eq!(trie.get("dir/3/file-48003"), None);
+        assert_eq!(trie.rank("dir/0/file-49000"), 0);
+
+        trie.shrink_to_fit();
+        assert!(trie.arena.len() <= 2 * 1000 + 1);
+        assert_eq!(trie.keys().count(), 1000);
+    }
+
+    struct Size;
+
+    impl Aggregate<u64> for Size {
+        type Value = u64;
+
+        fn empty() -> u64 {
+            0
+        }
+
+        fn value(value: &u64) -> u64 {
+            *value
+        }
+
+        fn combine(left: &u64, right: &u64) -> u64 {
+            left + right
+        }
+    }
+
+    #[test]
+    fn aggregate_prefix() {
+        let mut trie = PathTrie::<u64, Size>::default();
+
+        trie.insert("src/lib.rs", 100);
+        trie.insert("src/trie.rs", 20);
+        trie.insert("src/node/mod.rs", 3);
+        trie.insert("srcs", 4000);
+        trie.insert("README.md", 50000);
+
+        assert_eq!(trie.aggregate_prefix(""), 54123);
+        assert_eq!(trie.aggregate_prefix("src/"), 123);
+        assert_eq!(trie.aggregate_prefix("src"), 4123);
+        assert_eq!(trie.aggregate_prefix("src/node"), 3);
+        assert_eq!(trie.aggregate_prefix("src/t"), 20);
+        assert_eq!(trie.aggregate_prefix("src/trie.rs"), 20);
+        assert_eq!(trie.aggregate_prefix("src/trie.rs/"), 0);
+        assert_eq!(trie.aggregate_prefix("lib"), 0);
+
+        trie.insert("src/lib.rs", 200);
+        trie.insert("src", 1);
+        assert_eq!(trie.aggregate_prefix("src/"), 223);
+        assert_eq!(trie.aggregate_prefix("src"), 4224);
+
+        trie.remove("src/trie.rs");
+        trie.remove("srcs");
+        assert_eq!(trie.aggregate_prefix("src"), 204);
+        assert_eq!(trie.aggregate_prefix("src/"), 203);
+        assert_eq!(trie.aggregate_prefix(""), 50204);
+
+        let mut other = PathTrie::<u64, Size>::default();
+        other.insert("src/node/mod.rs", 7);
+        other.insert("src/fst.rs", 10);
+        trie.merge(other, |_, a, b| a + b);
+        assert_eq!(trie.aggregate_prefix("src/"), 220);
+
+        let trie = PathTrie::<u64, Size>::from_sorted_iter(vec![("a/b", 1), ("a/c", 2), ("b", 3)]);
+        assert_eq!(trie.aggregate_prefix("a/"), 3);
+    }
+
+    #[test]
+    fn rank_past_stored_key() {
+        let mut trie = PathTrie::<u32>::new();
+        trie.insert("ab", 0);
+        trie.insert("b", 1);
+
+        assert_eq!(trie.rank("abc"), 1);
+        assert_eq!(trie.rank("ab"), 0);
+        assert_eq!(trie.rank("b/c"), 2);
+    }
+
+    #[test]
+    fn rank_and_select() {
+        let mut trie = PathTrie::<u32>::new();
+        let mut paths = vec![
+            "bacon/sandwich/hamburger",
+            "bacon/sandwich/ham",
+            "bacon",
+            "bacon/sandwich/ham-replacement",
+            "bacon/baguette/croissant",
+            "bacon/jam",
+            "bacon/wat",
+            "break-everything/haha",
+            "",
+        ];
+        for (n, path) in paths.iter().enumerate() {
+            trie.insert(path, n as u32);
+        }
+        trie.insert("bacon/jam", 100);
+        paths.sort_unstable();
+
+        assert_eq!(trie.len(), paths.len());
+        for (n, path) in paths.iter().enumerate() {
+            assert_eq!(trie.rank(path), n);
+            assert_eq!(&*trie.select(n).unwrap().0, path.as_bytes());
+        }
+        assert_eq!(trie.select(paths.len()), None);
+        assert_eq!(trie.select(3).unwrap().1, &100);
+
+        for missing in [
+            "a",
+            "bacon/",
+            "bacon/sandwich",
+            "bacon/x",
+            "bz",
+            "bacon/jab",
+            "bacon/wat/er",
+            "break-everything/haha/ha",
+        ]
+        .iter()
+        {
+            let rank = paths.iter().filter(|x| *x < missing).count();
+            assert_eq!(trie.rank(missing), rank, "{}", missing);
+        }
+
+        trie.remove("bacon/jam");
+        assert_eq!(trie.len(), paths.len() - 1);
+        assert_eq!(trie.rank("bacon/wat"), 6);
+        assert!(!trie.is_empty());
+        assert!(PathTrie::<u32>::new().is_empty());
+    }
+
     #[test]
     fn fst() {
         let mut trie = PathTrie::<u32>::new();
@@ -523,15 +1581,15 @@ mod tests {
             assert_eq!(trie.keys().count(), n + 1);
         }
 
-        println!("ROOT: {:#?}", trie.root);
+        println!("ROOT: {:#?}", trie.root());
         trie.raw_entries().for_each(|x| {
             println!(
                 "D:{} {:?} [{}<>{}] {:?}",
                 x.depth,
                 x.ty,
                 String::from_utf8_lossy(&x.parent),
-                String::from_utf8_lossy(&x.node.key),
-                &x.node.body
+                String::from_utf8_lossy(x.node.key()),
+                x.node.body()
             )
         });
 
@@ -549,6 +1607,110 @@ mod tests {
         }
     }
 
+    #[test]
+    fn long_keys_fst() {
+        let mut trie = PathTrie::<u32>::new();
+        let long = "x".repeat(300);
+        let paths = [
+            format!("node_modules/{}/index.js", long),
+            format!("node_modules/{}/package.json", long),
+            format!("{}/{}", long, "y".repeat(20_000)),
+            "node_modules".to_string(),
+        ];
+
+        for (n, path) in paths.iter().enumerate() {
+            trie.insert(path, n as u32);
+        }
+
+        let mut buf = Cursor::new(vec![]);
+        trie.write_fst(&mut buf).unwrap();
+
+        std::fs::write("./test-long.fst", buf.into_inner()).unwrap();
+        let mmap = unsafe { Mmap::map(&File::open("./test-long.fst").unwrap()).unwrap() };
+        let fst = fst::Fst::<u32>::new(&mmap).unwrap();
+
+        for path in paths.iter() {
+            assert_eq!(trie.get(path), fst.get(path));
+        }
+        assert_eq!(fst.get(&long), None);
+    }
+
+    fn round_trip<T: Integer, O: fst::Offset>(name: &str) {
+        let mut trie = PathTrie::<T>::new();
+        let paths = ["a/1", "a/2", "a", "b/1/c", "bb", "c/1", "c/2/d/e"];
+        for path in paths.iter() {
+            trie.insert(path, T::try_from(path.len() as u64).ok().unwrap());
+        }
+
+        // A Vec can't seek, so this only works as the writer never needs to.
+        let mut buf = vec![];
+        trie.write_fst_with_offsets::<O, _>(&mut buf).unwrap();
+        let len = trie.fst_layout::<T, O, _>(|_, _| {});
+        assert_eq!(buf.len() as u64, len);
+
+        std::fs::write(name, buf).unwrap();
+        let mmap = unsafe { Mmap::map(&File::open(name).unwrap()).unwrap() };
+        let fst = fst::Fst::<T, O>::new(&mmap).unwrap();
+
+        for path in paths.iter().chain(["a/", "b", "c/2/d"].iter()) {
+            assert_eq!(trie.get(path), fst.get(path));
+        }
+        if size_of::<O>() != size_of::<u32>() {
+            assert!(fst::Fst::<T, u32>::new(&mmap).is_err());
+        }
+    }
+
+    #[test]
+    fn fst_widths() {
+        round_trip::<u8, u32>("./test-u8.fst");
+        round_trip::<u16, u64>("./test-u16.fst");
+        round_trip::<u64, u32>("./test-u64.fst");
+        round_trip::<u128, u32>("./test-u128.fst");
+        round_trip::<u128, u64>("./test-u128-wide.fst");
+    }
+
+    #[test]
+    fn fst_offset_overflow() {
+        let mut trie = PathTrie::<u8>::new();
+        for n in 0..10_000u32 {
+            trie.insert(format!("dir-{}/file-{}", n, n), n as u8);
+        }
+
+        // Offsets don't depend on the value width, only on where groups start.
+        let mut buf = vec![];
+        match trie.write_fst_with_offsets::<u16, _>(&mut buf) {
+            Err(fst::WriteError::OffsetOverflow { offset, width: 2 }) => {
+                assert!(offset > u16::MAX as u64)
+            }
+            other => panic!("Expected an offset overflow, got {:?}", other),
+        }
+
+        trie.write_fst_with_offsets::<u32, _>(&mut buf).unwrap();
+        assert!(buf.len() > u16::MAX as usize);
+    }
+
+    #[test]
+    fn fst_to_slice() {
+        let mut trie = PathTrie::<u64>::new();
+        for (n, path) in ["a/b", "a/c", "a", "d"].iter().enumerate() {
+            trie.insert(path, n as u64);
+        }
+
+        let mut expected = vec![];
+        trie.write_fst(&mut expected).unwrap();
+        assert_eq!(trie.fst_size(), expected.len());
+
+        let mut buf = vec![0xaa; trie.fst_size() + 3];
+        assert_eq!(trie.write_fst_to_slice(&mut buf).unwrap(), expected.len());
+        assert_eq!(&buf[..expected.len()], &*expected);
+        assert_eq!(&buf[expected.len()..], &[0xaa; 3]);
+
+        match trie.write_fst_to_slice(&mut buf[..10]) {
+            Err(fst::WriteError::BufferTooSmall { got: 10, .. }) => {}
+            other => panic!("Expected a small buffer error, got {:?}", other),
+        }
+    }
+
     #[test]
     fn set_and_get() {
         let mut trie = PathTrie::<u32>::new();
@@ -582,7 +1744,7 @@ mod tests {
         assert_eq!(trie.get("b"), None);
 
         assert_eq!(trie.keys().count(), paths.len());
-        println!("{}", &trie.root);
+        println!("{}", trie.root());
 
         let mut buf = Cursor::new(vec![]);
         trie.write_fst(&mut buf).unwrap();
@@ -598,6 +1760,49 @@ mod tests {
         }
     }
 
+    #[test]
+    fn merge() {
+        let mut ours = PathTrie::<u32>::new();
+        let mut theirs = PathTrie::<u32>::new();
+
+        for (n, path) in ["bacon/sandwich/ham", "bacon/jam", "apple", "car"]
+            .iter()
+            .enumerate()
+        {
+            ours.insert(path, n as u32 + 1);
+        }
+
+        for (n, path) in [
+            "bacon/sandwich/hamburger",
+            "bacon/jam",
+            "bacon",
+            "app",
+            "cart",
+        ]
+        .iter()
+        .enumerate()
+        {
+            theirs.insert(path, n as u32 + 10);
+        }
+
+        let mut conflicts = vec![];
+        ours.merge(theirs, |key, a, b| {
+            conflicts.push(String::from_utf8_lossy(key).to_string());
+            a + b
+        });
+
+        assert_eq!(conflicts, vec!["bacon/jam"]);
+        assert_eq!(ours.keys().count(), 8);
+        assert_eq!(ours.get("bacon/sandwich/ham"), Some(1));
+        assert_eq!(ours.get("bacon/sandwich/hamburger"), Some(10));
+        assert_eq!(ours.get("bacon/jam"), Some(2 + 11));
+        assert_eq!(ours.get("bacon"), Some(12));
+        assert_eq!(ours.get("apple"), Some(3));
+        assert_eq!(ours.get("app"), Some(13));
+        assert_eq!(ours.get("car"), Some(4));
+        assert_eq!(ours.get("cart"), Some(14));
+    }
+
     #[test]
     fn empty_fst() {
         let trie = PathTrie::<u32>::new();